@@ -0,0 +1,28 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use segtree_native::core::SegmentTree;
+
+const N_POINTS: usize = 100_000;
+
+fn point_insertions(c: &mut Criterion) {
+    let ends: Vec<f64> = (0..N_POINTS).map(|x| x as f64).collect();
+    let tree = SegmentTree::new(ends).unwrap();
+    c.bench_function("add_segment 100k points", |b| {
+        b.iter_batched_ref(
+            || SegmentTree::new(tree.endpoints()).unwrap(),
+            |tree| {
+                for i in 0..N_POINTS {
+                    let v = i as f64;
+                    tree.add_segment(black_box((v, v)), i).unwrap();
+                }
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = point_insertions
+}
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use segtree_native::core::SegmentTree;
+
+const N_IDS: usize = 10_000;
+
+// ids in a scattered but deterministic order, so neither layout gets a sorted-input shortcut
+fn scattered_ids() -> Vec<usize> {
+    (0..N_IDS).map(|i| i * 7919 % N_IDS).collect()
+}
+
+// the old and new `Node.segs` layouts on the same workload, then the tree end to end, where
+// all ids land on the single point leaf [1, 1]
+fn single_node_insert_remove(c: &mut Criterion) {
+    let ends: Vec<f64> = (0..1024).map(|x| x as f64).collect();
+    let ids = scattered_ids();
+    let mut group = c.benchmark_group("10k ids at one deep node");
+    group.sample_size(10);
+    group.bench_function("old: unsorted Vec push + retain", |b| {
+        b.iter(|| {
+            let mut segs: Vec<usize> = Vec::new();
+            for &id in &ids {
+                segs.push(black_box(id));
+            }
+            for &id in &ids {
+                segs.retain(|&x| x != black_box(id));
+            }
+            segs
+        })
+    });
+    group.bench_function("new: sorted Vec binary-search insert + drain", |b| {
+        b.iter(|| {
+            let mut segs: Vec<usize> = Vec::new();
+            for &id in &ids {
+                let id = black_box(id);
+                let pos = segs.partition_point(|&x| x < id);
+                segs.insert(pos, id);
+            }
+            for &id in &ids {
+                let id = black_box(id);
+                let range = segs.partition_point(|&x| x < id)..segs.partition_point(|&x| x <= id);
+                segs.drain(range);
+            }
+            segs
+        })
+    });
+    group.bench_function("SegmentTree add + remove", |b| {
+        b.iter_batched_ref(
+            || SegmentTree::new(ends.clone()).unwrap(),
+            |tree| {
+                for &id in &ids {
+                    tree.add_segment(black_box((1.0, 1.0)), id).unwrap();
+                }
+                for &id in &ids {
+                    tree.remove_segment(black_box((1.0, 1.0)), id).unwrap();
+                }
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// small tree: reserve_segments applies to every node
+fn reserved_insert(c: &mut Criterion) {
+    let ends: Vec<f64> = (0..16).map(|x| x as f64).collect();
+    let mut group = c.benchmark_group("10k ids at one node, reserved");
+    group.sample_size(10);
+    for reserve in [false, true] {
+        let name = if reserve { "reserve_segments" } else { "no reserve" };
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || {
+                    let mut tree = SegmentTree::new(ends.clone()).unwrap();
+                    if reserve {
+                        tree.reserve_segments(N_IDS);
+                    }
+                    tree
+                },
+                |tree| {
+                    for id in 0..N_IDS {
+                        tree.add_segment(black_box((1.0, 1.0)), id).unwrap();
+                    }
+                },
+                criterion::BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, single_node_insert_remove, reserved_insert);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use segtree_native::core::SegmentTree;
+
+const N_ENDS: usize = 100_000;
+const N_QUERIES: usize = 1_000;
+
+// uncovered subtrees are pruned, so sparse trees should answer in far fewer node visits; this
+// only times it, the visit counts are asserted in core's test_union_sparse_visits
+fn union_queries(c: &mut Criterion) {
+    let ends: Vec<f64> = (0..N_ENDS).map(|x| x as f64).collect();
+    let empty = SegmentTree::new(ends.clone()).unwrap();
+    let mut sparse = SegmentTree::new(ends.clone()).unwrap();
+    let mut dense = SegmentTree::new(ends).unwrap();
+    for i in 0..10 {
+        let start = (i * N_ENDS / 10) as f64;
+        sparse.add_segment((start, start + 5.0), i).unwrap();
+    }
+    for i in 0..N_ENDS / 2 {
+        let start = (2 * i) as f64;
+        dense.add_segment((start, start + 1.0), i).unwrap();
+    }
+    let queries: Vec<(f64, f64)> = (0..N_QUERIES)
+        .map(|i| {
+            let start = (i * 97 % N_ENDS) as f64;
+            (start, start + 1000.0)
+        })
+        .collect();
+    let mut group = c.benchmark_group("get_union 1k queries of width 1000");
+    group.sample_size(10);
+    for (name, tree) in [("empty", &empty), ("sparse", &sparse), ("dense", &dense)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for &query in &queries {
+                    black_box(tree.get_union(black_box(query), false, true).unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, union_queries);
+criterion_main!(benches);
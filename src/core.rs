@@ -1,16 +1,96 @@
 use core::f64;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt::{self, Write};
 use std::vec;
 pub use crate::util::input::*;
 
-#[derive(Default, Clone)]
+// nodes entered by union_visit on this thread, so tests can check the pruning bound
+#[cfg(test)]
+thread_local! {
+    static UNION_VISITS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[derive(Default, Clone, PartialEq)]
 pub struct Node {
-    segs: Vec<usize>,
+    segs: Vec<usize>, // kept sorted
     itv: (f64, f64)
 }
 
+impl Node {
+    fn seg_range(&self, id: usize) -> std::ops::Range<usize> {
+        self.segs.partition_point(|&x| x < id)..self.segs.partition_point(|&x| x <= id)
+    }
+
+    fn insert_seg(&mut self, id: usize) {
+        let pos = self.segs.partition_point(|&x| x < id);
+        self.segs.insert(pos, id);
+    }
+
+    // removes every copy of id, returns how many there were
+    fn remove_seg(&mut self, id: usize) -> usize {
+        let range = self.seg_range(id);
+        let count = range.len();
+        self.segs.drain(range);
+        count
+    }
+
+    fn has_seg(&self, id: usize) -> bool {
+        self.segs.binary_search(&id).is_ok()
+    }
+}
+
+/// Plain owned data, so the tree is `Send + Sync`: queries take `&self` and can run
+/// concurrently (e.g. behind an `Arc`), while mutation requires exclusive `&mut` access.
+///
+/// Equality compares the whole state: endpoints, the ids stored on every node, node stats, original
+/// intervals, the lazy flag and the snapping epsilon. Node ids are kept sorted, so insertion order does not matter, but a
+/// tree with deferred stats pending differs from its recomputed self.
+#[derive(PartialEq)]
 pub struct SegmentTree {
     nodes: Vec<Node>,
-    stats: Vec<Stats>
+    stats: Vec<Stats>,
+    originals: HashMap<usize, (f64, f64)>, // interval last added per id; canonical storage loses it
+    n_slots: usize, // (node, id) storage slots, kept in step with every insert_seg/remove_seg
+    lazy: bool, // see new_lazy
+    epsilon: f64 // see new_with_epsilon
+}
+
+// below this many nodes a parallel build costs more than it saves
+#[cfg(feature = "rayon")]
+const PAR_MIN_NODES: usize = 1 << 14;
+
+#[derive(Clone)]
+pub struct TreeSnapshot {
+    nodes: Vec<Node>,
+    stats: Vec<Stats>,
+    originals: HashMap<usize, (f64, f64)>
+}
+
+/// What `to_state` exports: endpoints, `(node index, id)` storage pairs, `(id, interval)` originals
+/// whether the tree is lazy and its snapping epsilon.
+pub type TreeState = (Vec<f64>, Vec<(usize, usize)>, Vec<(usize, (f64, f64))>, bool, f64);
+
+/// Collects endpoints one at a time, validating as they come, and sorts once in `finish`.
+#[derive(Default)]
+pub struct SegmentTreeBuilder {
+    ends: Vec<f64>
+}
+
+impl SegmentTreeBuilder {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { ends: Vec::with_capacity(capacity) }
+    }
+
+    pub fn push_end(&mut self, end: f64) -> Result<(), InputError> {
+        end.validate()?;
+        self.ends.push(end);
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<SegmentTree, InputError> {
+        SegmentTree::from_valid_ends(self.ends, Some(0.0))
+    }
 }
 
 trait ChildIdUnchecked { fn left(self) -> Self; fn right(self) -> Self; }
@@ -22,7 +102,10 @@ impl ChildIdUnchecked for usize {
 
 trait Interval {
     fn overlaps(self, other: Self) -> bool;
+    fn reaches(self, seg: Self) -> bool;
+    fn meets(self, other: Self) -> bool;
     fn contains(self, other: Self) -> bool;
+    fn shares_point(self, other: Self) -> bool;
 }
 
 impl Interval for (f64, f64) {
@@ -30,16 +113,48 @@ impl Interval for (f64, f64) {
     fn overlaps(self, other: Self) -> bool {
         self.0 < other.1 && other.0 < self.1
     }
-    fn contains(self, other: Self) -> bool {
-        if other.0.is_infinite() || other.1.is_infinite() {
-            return false;
+    // a point seg (v,v) must still descend to the point leaf [v,v]
+    #[inline]
+    fn reaches(self, seg: Self) -> bool {
+        if seg.0 == seg.1 {
+            return self.0 <= seg.0 && seg.1 <= self.1;
         }
+        self.overlaps(seg)
+    }
+    // closed intersection: a segment ending at v must reach the subtree holding [v, v]
+    #[inline]
+    fn meets(self, other: Self) -> bool {
+        self.0 <= other.1 && other.0 <= self.1
+    }
+    fn contains(self, other: Self) -> bool {
         self.0 <= other.0 && other.1 <= self.1
     }
+    // intersection of stored segments: half-open (start, end], a point segment (v,v) is [v, v]
+    #[inline]
+    fn shares_point(self, other: Self) -> bool {
+        let starts_before = |a: Self, end: f64| if a.0 == a.1 { a.0 <= end } else { a.0 < end };
+        starts_before(self, other.1) && starts_before(other, self.1)
+    }
+}
+
+// the leaves a stored segment covers: `itv` plus whether each end takes the point leaf there.
+// Segments are half-open `(start, end]`, a point segment `(v, v)` is the point leaf `[v, v]`
+#[derive(Clone, Copy)]
+struct Span {
+    itv: (f64, f64),
+    closed: (bool, bool),
+}
+
+impl From<(f64, f64)> for Span {
+    fn from(itv: (f64, f64)) -> Self {
+        Span { itv, closed: (itv.0 == itv.1, true) }
+    }
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, PartialEq)]
 pub struct Stats {
+    /// Covered length inside the node's interval: its full span if it stores a segment,
+    /// otherwise the sum over its children. Not the span of the node itself.
     pub length: f64,
     pub max_ovp: usize,
     pub min_ovp: usize,
@@ -50,6 +165,23 @@ pub struct Union{
 }
 
 impl Union {
+    fn push_coalesced(&mut self, itv: (f64, f64)) {
+        match self.intervals.last_mut() {
+            Some(last_itv) if last_itv.1 == itv.0 => last_itv.1 = itv.1,
+            _ => self.intervals.push(itv),
+        }
+    }
+
+    fn coalesce(&mut self) {
+        self.intervals.dedup_by(|itv, last_itv| {
+            let touching = last_itv.1 == itv.0;
+            if touching {
+                last_itv.1 = itv.1;
+            }
+            touching
+        });
+    }
+
     pub fn contains_point(&self, query: f64) -> bool {
         if query.is_nan() || query.is_infinite() {
             return false;
@@ -65,44 +197,365 @@ impl Union {
         });
         pos.is_ok()
     }
+
+    /// Strict `contains_point`: a NaN query is an error instead of `false`.
+    pub fn try_contains_point(&self, query: f64) -> Result<bool, InputError> {
+        query.validate_inf()?;
+        Ok(self.contains_point(query))
+    }
+
+    /// Whether `query` lies inside a single stored interval. Relies on the intervals being
+    /// disjoint and sorted, with touching runs already merged by coalescing.
+    pub fn contains_interval(&self, query: (f64, f64)) -> bool {
+        if query.0.is_nan() || query.1.is_nan() || query.0 > query.1 {
+            return false;
+        }
+        let pos = self.intervals.partition_point(|itv| itv.1 < query.0);
+        self.intervals.get(pos).is_some_and(|itv| itv.0 <= query.0 && query.1 <= itv.1)
+    }
+
+    /// The interval containing `from`, or else the first one to its right.
+    pub fn next_interval(&self, from: f64) -> Result<Option<(f64, f64)>, InputError> {
+        from.validate_inf()?;
+        let pos = self.intervals.partition_point(|itv| itv.1 < from);
+        Ok(self.intervals.get(pos).copied())
+    }
+
+    pub fn length(&self) -> f64 {
+        self.intervals.iter().map(|itv| itv.1 - itv.0).sum()
+    }
+
+    /// Positive-length overlap of the two unions.
+    pub fn intersection(&self, other: &Union) -> Union {
+        let mut out = Union { intervals: Vec::new() };
+        let (mut i, mut j) = (0, 0);
+        while i < self.intervals.len() && j < other.intervals.len() {
+            let (a, b) = (self.intervals[i], other.intervals[j]);
+            let (lo, hi) = (a.0.max(b.0), a.1.min(b.1));
+            if lo < hi {
+                out.intervals.push((lo, hi));
+            }
+            if a.1 < b.1 { i += 1; } else { j += 1; }
+        }
+        out
+    }
+
+    pub fn union(&self, other: &Union) -> Union {
+        let mut merged: Vec<(f64, f64)> = self.intervals.iter().chain(other.intervals.iter()).copied().collect();
+        merged.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut out = Union { intervals: Vec::with_capacity(merged.len()) };
+        for itv in merged {
+            match out.intervals.last_mut() {
+                Some(last_itv) if itv.0 <= last_itv.1 => last_itv.1 = last_itv.1.max(itv.1),
+                _ => out.intervals.push(itv),
+            }
+        }
+        out
+    }
+
+    /// Positive-length parts of `self` not covered by `other`.
+    pub fn difference(&self, other: &Union) -> Union {
+        let mut out = Union { intervals: Vec::new() };
+        let mut j = 0;
+        for &(mut lo, hi) in &self.intervals {
+            while j < other.intervals.len() && other.intervals[j].1 <= lo {
+                j += 1;
+            }
+            for cut in other.intervals[j..].iter().take_while(|cut| cut.0 < hi) {
+                if lo < cut.0 {
+                    out.intervals.push((lo, cut.0));
+                }
+                lo = lo.max(cut.1);
+            }
+            if lo < hi {
+                out.intervals.push((lo, hi));
+            }
+        }
+        out
+    }
+}
+
+/// Covered runs of constant overlap depth as `(start, end, depth)`.
+pub struct DepthUnion {
+    pub intervals: Vec<(f64, f64, usize)>
+}
+
+impl DepthUnion {
+    fn push_coalesced(&mut self, itv: (f64, f64), depth: usize) {
+        match self.intervals.last_mut() {
+            Some(last) if last.1 == itv.0 && last.2 == depth => last.1 = itv.1,
+            _ => self.intervals.push((itv.0, itv.1, depth)),
+        }
+    }
+
+    /// Overlap depth at `query`, 0 if uncovered. On a boundary shared by two runs the larger depth wins.
+    pub fn depth_at(&self, query: f64) -> usize {
+        if query.is_nan() || query.is_infinite() {
+            return 0;
+        }
+        let start = self.intervals.partition_point(|itv| itv.1 < query);
+        self.intervals[start..].iter()
+            .take_while(|itv| itv.0 <= query)
+            .map(|itv| itv.2)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// explicit-stack version of union_visit (open query, min_depth 1) that coalesces as it goes
+struct UnionIter<'a> {
+    tree: &'a SegmentTree,
+    seg: (f64, f64),
+    stack: Vec<(usize, usize)>, // (node id, segments stored on its ancestors)
+    pending: Option<(f64, f64)>,
+}
+
+impl Iterator for UnionIter<'_> {
+    type Item = (f64, f64);
+
+    fn next(&mut self) -> Option<(f64, f64)> {
+        let (tree, seg) = (self.tree, self.seg);
+        while let Some((node_id, acc)) = self.stack.pop() {
+            let node = &tree.nodes[node_id];
+            let stats = &tree.stats[node_id];
+            if acc + stats.max_ovp == 0 {
+                continue;
+            }
+            if acc + stats.min_ovp > 0 {
+                let itv = (node.itv.0.max(seg.0), node.itv.1.min(seg.1));
+                if itv.0 < itv.1 || node.itv.0 == node.itv.1 {
+                    let on_boundary = itv.0 == itv.1 && (itv.0 == seg.0 || itv.1 == seg.1);
+                    if !on_boundary {
+                        match self.pending.as_mut() {
+                            Some(last) if last.1 >= itv.0 => last.1 = last.1.max(itv.1),
+                            _ => if let Some(done) = self.pending.replace(itv) {
+                                return Some(done);
+                            }
+                        }
+                    }
+                    continue;
+                }
+            }
+            if tree.is_leaf(node_id) {
+                continue;
+            }
+            let acc = acc + node.segs.len();
+            for child in [node_id.right(), node_id.left()] {
+                if tree.nodes[child].itv.reaches(seg) {
+                    self.stack.push((child, acc));
+                }
+            }
+        }
+        self.pending.take()
+    }
 }
 
 impl SegmentTree {
 
-    fn build(&mut self, ends: &[f64]) {
-        let n_leaves = (ends.len() << 1) | 1;
-        let n_nodes_compl = (n_leaves + 1).next_power_of_two() >> 1; // nodes in last complete level
-        let n_leaves_last = (n_leaves - n_nodes_compl) << 1; // leaves in last(incomplete) level
-        let n_leaves_compl = n_leaves - n_leaves_last; // leaves in last complete level
-        let compl_tree_size = (n_nodes_compl << 1) - 1; // nodes in complete levels
-        let tree_size = compl_tree_size + n_leaves_last; // total nodes
-        self.nodes = vec![Node::default(); tree_size];
-        let p_leaves_compl_start = compl_tree_size - n_leaves_compl;
-        let p_last_start = compl_tree_size;
-        let leftest_leaf_id = if n_leaves_last == 0 { p_leaves_compl_start } else { p_last_start };
-        // set leaf intervals
-        self.nodes[leftest_leaf_id].itv.0 = f64::NEG_INFINITY;
-        let leaf_ids:Vec<usize> = (p_last_start..tree_size).chain(p_leaves_compl_start..p_last_start).collect();
-        for (i,&v) in ends.iter().enumerate() {
-            let seg_prev = &mut self.nodes[leaf_ids[i << 1]];
-            seg_prev.itv.1 = v;
-            let point = &mut self.nodes[leaf_ids[(i << 1) | 1]];
-            point.itv.0 = v;
-            point.itv.1 = v;
-            let seg_next = &mut self.nodes[leaf_ids[(i << 1) + 2]];
-            seg_next.itv.0 = v;
-        }
-        self.nodes[p_last_start-1].itv.1 = f64::INFINITY;
-        // intervals for internal nodes
-        if tree_size == 1 { return; }
-        for i in (0..(tree_size-n_leaves)).rev() { 
+    // epsilon None keeps duplicate endpoints
+    // folds -0.0 into 0.0, which sorts apart from it under total_cmp
+    fn normalize_ends(all_ends: &mut [f64]) -> Result<(), InputError> {
+        for end in all_ends.iter_mut().filter(|end| **end == 0.0) {
+            *end = 0.0;
+        }
+        all_ends.validate()
+    }
+
+    fn from_valid_ends(mut all_ends: Vec<f64>, epsilon: Option<f64>) -> Result<Self, InputError> {
+        Self::normalize_ends(&mut all_ends)?;
+        #[cfg(feature = "rayon")]
+        rayon::slice::ParallelSliceMut::par_sort_by(all_ends.as_mut_slice(), f64::total_cmp);
+        #[cfg(not(feature = "rayon"))]
+        all_ends.sort_by(f64::total_cmp);
+        if let Some(epsilon) = epsilon {
+            all_ends.dedup_by(|end, representative| *end - *representative <= epsilon);
+        }
+        Self::with_ends(&all_ends)
+    }
+
+    fn validate_epsilon(epsilon: f64) -> Result<(), InputError> {
+        epsilon.validate()?;
+        if epsilon < 0.0 {
+            return Err(InputError::NegativeEpsilon);
+        }
+        Ok(())
+    }
+
+    fn validate_bounded(interval: (f64, f64)) -> Result<(), InputError> {
+        interval.validate_inf()?;
+        if interval.0.is_infinite() {
+            return Err(InputError::InfiniteSegmentEnd("start"));
+        }
+        if interval.1.is_infinite() {
+            return Err(InputError::InfiniteSegmentEnd("end"));
+        }
+        Ok(())
+    }
+
+    // with an epsilon, a bound within it of an endpoint moves onto the nearest one, found on the
+    // root-to-leaf path
+    fn snap_point(&self, x: f64) -> f64 {
+        if self.epsilon == 0.0 || x.is_infinite() {
+            return x;
+        }
+        let mut node_id = 0;
+        while !self.is_leaf(node_id) {
+            let (left, right) = (node_id.left(), node_id.right());
+            if x < self.nodes[left].itv.1 {
+                node_id = left;
+            } else if x > self.nodes[right].itv.0 {
+                node_id = right;
+            } else {
+                // on the shared boundary, which is an endpoint
+                return x;
+            }
+        }
+        let (lo, hi) = self.nodes[node_id].itv;
+        let nearest = if x - lo <= hi - x { lo } else { hi };
+        if (x - nearest).abs() <= self.epsilon { nearest } else { x }
+    }
+
+    fn snap(&self, interval: (f64, f64)) -> (f64, f64) {
+        (self.snap_point(interval.0), self.snap_point(interval.1))
+    }
+
+    fn with_ends(ends: &[f64]) -> Result<Self, InputError> {
+        let mut tree = Self { nodes: Vec::new(), stats: Vec::new(), originals: HashMap::new(), n_slots: 0, lazy: false, epsilon: 0.0 };
+        tree.build(ends)?;
+        tree.stats = vec![Stats::default(); tree.nodes.len()];
+        Ok(tree)
+    }
+
+    // 2n + 1 leaves and 2 * leaves - 1 nodes, checked so huge inputs fail instead of wrapping
+    // around to an undersized allocation
+    fn tree_size(n_ends: usize) -> Result<usize, InputError> {
+        let n_leaves = n_ends.checked_mul(2).and_then(|x| x.checked_add(1)).ok_or(InputError::TooLarge)?;
+        let tree_size = n_leaves.checked_mul(2).ok_or(InputError::TooLarge)? - 1;
+        let node_bytes = std::mem::size_of::<Node>() + std::mem::size_of::<Stats>();
+        match tree_size.checked_mul(node_bytes) {
+            Some(bytes) if bytes <= isize::MAX as usize => Ok(tree_size),
+            _ => Err(InputError::TooLarge),
+        }
+    }
+
+    // rebuilds with the finite ends of interval added, copying the ids of every old node onto the
+    // new nodes that cover the same leaves, so storage (duplicates and partial removals) is kept
+    fn insert_ends(&mut self, interval: (f64, f64)) -> Result<(), InputError> {
+        let mut ends = self.endpoints();
+        let missing: Vec<f64> = [interval.0, interval.1].into_iter()
+            .filter(|end| end.is_finite() && !ends.contains(end))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        ends.extend(missing);
+        let mut old = std::mem::replace(self, Self::from_valid_ends(ends, Some(0.0))?);
+        self.lazy = old.lazy;
+        self.epsilon = old.epsilon;
+        self.originals = std::mem::take(&mut old.originals);
+        // each old node's leaves are a run of new leaves, so its ids go onto that run's cover
+        for (i, node) in old.nodes.iter().enumerate() {
+            if node.segs.is_empty() {
+                continue;
+            }
+            let span = Span {
+                itv: node.itv,
+                closed: (old.edge_leaf_is_point(i, false), old.edge_leaf_is_point(i, true)),
+            };
+            for &id in &node.segs {
+                self.seg_nodes_apply(span, &mut |s, i| {
+                    s.nodes[i].insert_seg(id);
+                    s.n_slots += 1;
+                }, 0);
+            }
+        }
+        Ok(())
+    }
+
+    // applies the increasing map f to every finite node bound and original interval. Checked
+    // up front so an Err leaves the tree unchanged: mapped coordinates must stay finite and
+    // distinct endpoints must stay distinct
+    fn map_coords(&mut self, f: impl Fn(f64) -> f64) -> Result<(), InputError> {
+        let ends = self.endpoints();
+        let mut mapped: Vec<f64> = ends.iter().map(|&end| f(end)).collect();
+        Self::normalize_ends(&mut mapped)?;
+        if ends.windows(2).zip(mapped.windows(2)).any(|(old, new)| old[0] != old[1] && new[0] == new[1]) {
+            return Err(InputError::UnsortedEndpoints);
+        }
+        let map = |x: f64| if x.is_finite() { f(x) } else { x };
+        for &(start, end) in self.originals.values() {
+            if (start.is_finite() && map(start).is_infinite()) || (end.is_finite() && map(end).is_infinite()) {
+                return Err(InputError::ContainsInfinite);
+            }
+        }
+        for node in self.nodes.iter_mut() {
+            let (lo, hi) = node.itv;
+            node.itv = (map(lo), map(hi));
+        }
+        for itv in self.originals.values_mut() {
+            *itv = (map(itv.0), map(itv.1));
+        }
+        self.recompute_stats();
+        Ok(())
+    }
+
+    fn build(&mut self, ends: &[f64]) -> Result<(), InputError> {
+        self.nodes = vec![Node::default(); Self::tree_size(ends.len())?];
+        // set leaf intervals: (-inf, e0), [e0, e0], (e0, e1), ..., [en, en], (en, +inf)
+        let mut lo = f64::NEG_INFINITY;
+        for (k, leaf_id) in self.leaves_in_order().enumerate() {
+            let itv = &mut self.nodes[leaf_id].itv;
+            if k & 1 == 1 {
+                lo = ends[k >> 1];
+                *itv = (lo, lo);
+            } else {
+                *itv = (lo, ends.get(k >> 1).copied().unwrap_or(f64::INFINITY));
+            }
+        }
+        self.set_internal_bounds();
+        Ok(())
+    }
+
+    // intervals for internal nodes
+    fn set_internal_bounds(&mut self) {
+        #[cfg(feature = "rayon")]
+        if self.nodes.len() >= PAR_MIN_NODES {
+            return self.set_internal_bounds_par();
+        }
+        self.set_internal_bounds_seq();
+    }
+
+    fn set_internal_bounds_seq(&mut self) {
+        for i in (0..(self.nodes.len() >> 1)).rev() { 
             self.nodes[i].itv.0 = self.nodes[i.left()].itv.0;
             self.nodes[i].itv.1 = self.nodes[i.right()].itv.1;
         }
     }
 
-    fn seg_nodes_apply(&mut self, seg: (f64, f64), op: &mut dyn FnMut(&mut Self, usize), node_id: usize) {
-        if seg.contains(self.nodes[node_id].itv) {
+    // same, one tree level at a time: level d holds nodes 2^d - 1 .. 2^(d+1) - 1 and only reads
+    // its children on level d + 1, so each level is split off and filled in parallel
+    #[cfg(feature = "rayon")]
+    fn set_internal_bounds_par(&mut self) {
+        use rayon::prelude::*;
+        let n_internal = self.nodes.len() >> 1;
+        if n_internal == 0 {
+            return;
+        }
+        for level in (0..=n_internal.ilog2()).rev() {
+            let (start, next) = ((1usize << level) - 1, (1usize << (level + 1)) - 1);
+            let (upper, lower) = self.nodes.split_at_mut(next);
+            upper[start..next.min(n_internal)].par_iter_mut().enumerate().for_each(|(k, node)| {
+                let i = start + k;
+                node.itv = (lower[i.left() - next].itv.0, lower[i.right() - next].itv.1);
+            });
+        }
+    }
+
+    // Each visited node gets its stats recomputed exactly once, after its children are done.
+    // A segment visits O(log n) nodes (two partial paths plus their canonical cover nodes),
+    // a point segment (v,v) just the O(depth) root-to-leaf path.
+    fn seg_nodes_apply(&mut self, seg: Span, op: &mut dyn FnMut(&mut Self, usize), node_id: usize) {
+        if self.span_covers(seg, node_id) {
             op(self, node_id);
             self.update_stats(node_id);
             return;
@@ -110,34 +563,85 @@ impl SegmentTree {
         if self.is_leaf(node_id) {
             return;
         }
-        if self.nodes[node_id.left()].itv.overlaps(seg){
+        if self.nodes[node_id.left()].itv.meets(seg.itv) {
             self.seg_nodes_apply(seg, op, node_id.left());
         }
-        if self.nodes[node_id.right()].itv.overlaps(seg){
+        if self.nodes[node_id.right()].itv.meets(seg.itv) {
             self.seg_nodes_apply(seg, op, node_id.right());
         }
         self.update_stats(node_id);
     }
 
-    fn seg_nodes_cond_visit(&self, seg: (f64, f64), report: &mut dyn FnMut(&Self, usize)->bool, node_id: usize) {
-        if seg.contains(self.nodes[node_id].itv) {
-            if !report(self, node_id) {
-                return;
-            }
+    fn seg_nodes_cond_visit(&self, seg: Span, report: &mut dyn FnMut(&Self, usize)->bool, node_id: usize) {
+        if self.span_covers(seg, node_id) && !report(self, node_id) {
+            return;
         }
         if self.is_leaf(node_id) {
             return;
         }
-        if self.nodes[node_id.left()].itv.overlaps(seg){
+        if self.nodes[node_id.left()].itv.meets(seg.itv) {
             self.seg_nodes_cond_visit(seg, report, node_id.left());
         }
-        if self.nodes[node_id.right()].itv.overlaps(seg){
+        if self.nodes[node_id.right()].itv.meets(seg.itv) {
             self.seg_nodes_cond_visit(seg, report, node_id.right());
         }
     }
 
-    fn update_stats(&mut self, node_id: usize) {
-        let node: &Node = &self.nodes[node_id];
+    // whether every leaf under the node is in the span. Node bounds do not tell a point leaf
+    // [v, v] from an open leaf starting or ending at v, so a bound equal to an open end of the
+    // span is settled by the leaf on that edge of the subtree
+    fn span_covers(&self, seg: Span, node_id: usize) -> bool {
+        let (lo, hi) = self.nodes[node_id].itv;
+        let left = seg.itv.0 < lo || (seg.itv.0 == lo && (seg.closed.0 || !self.edge_leaf_is_point(node_id, false)));
+        let right = hi < seg.itv.1 || (hi == seg.itv.1 && (seg.closed.1 || !self.edge_leaf_is_point(node_id, true)));
+        left && right
+    }
+
+    fn edge_leaf_is_point(&self, mut node_id: usize, rightmost: bool) -> bool {
+        while !self.is_leaf(node_id) {
+            node_id = if rightmost { node_id.right() } else { node_id.left() };
+        }
+        let (lo, hi) = self.nodes[node_id].itv;
+        lo == hi
+    }
+
+    fn overlap_nodes_visit(&self, seg: (f64, f64), report: &mut dyn FnMut(&Self, usize), node_id: usize) {
+        report(self, node_id);
+        if self.is_leaf(node_id) {
+            return;
+        }
+        if self.nodes[node_id.left()].itv.overlaps(seg){
+            self.overlap_nodes_visit(seg, report, node_id.left());
+        }
+        if self.nodes[node_id.right()].itv.overlaps(seg){
+            self.overlap_nodes_visit(seg, report, node_id.right());
+        }
+    }
+
+    fn any_overlap(&self, seg: (f64, f64), node_id: usize) -> bool {
+        if self.stats[node_id].max_ovp == 0 || !self.nodes[node_id].itv.overlaps(seg) {
+            return false;
+        }
+        if !self.nodes[node_id].segs.is_empty() {
+            return true;
+        }
+        !self.is_leaf(node_id)
+            && (self.any_overlap(seg, node_id.left()) || self.any_overlap(seg, node_id.right()))
+    }
+
+    fn leaves_depth_visit(&self, report: &mut dyn FnMut(&Self, usize, usize), node_id: usize, acc: usize) {
+        let acc = acc + self.nodes[node_id].segs.len();
+        if self.is_leaf(node_id) {
+            report(self, node_id, acc);
+            return;
+        }
+        self.leaves_depth_visit(report, node_id.left(), acc);
+        self.leaves_depth_visit(report, node_id.right(), acc);
+    }
+
+    // stats of the node from its own segments and the current stats of its children
+    fn fresh_stats(&self, node_id: usize) -> Stats {
+        let node = &self.nodes[node_id];
         let mut new_stats = Stats {
             min_ovp: node.segs.len(),
             max_ovp: node.segs.len(),
@@ -155,172 +659,2810 @@ impl SegmentTree {
                 new_stats.length = child_stats.0.length + child_stats.1.length;
             }
         }
-        self.stats[node_id] = new_stats;
-    }
-
-    #[inline]
-    fn is_leaf(&self, idx: usize) -> bool {
-        idx >= (self.nodes.len() >> 1)
-    }
-}
-
-impl SegmentTree {
-
-    pub fn new(mut all_ends: Vec<f64>) -> Result<Self, InputError> {
-        all_ends.validate()?;
-        let mut tree = Self { nodes: Vec::new(), stats: Vec::new() };
-        all_ends.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        all_ends.dedup();
-        tree.build(&all_ends);
-        tree.stats = vec![Stats::default(); tree.nodes.len()];
-        Ok(tree)
+        new_stats
     }
 
-    pub fn add_segment(&mut self, interval: (f64, f64), id: usize) -> Result<(), InputError> {
-        interval.validate()?;
-        self.seg_nodes_apply(interval,
-            &mut |s, i| {
-                s.nodes[i].segs.push(id)
-            }, 0);
-        Ok(())
+    fn update_stats(&mut self, node_id: usize) {
+        self.stats[node_id] = self.fresh_stats(node_id);
     }
 
-    pub fn remove_segment(&mut self, interval: (f64, f64), id: usize) -> Result<(), InputError> {
-        interval.validate()?;
-        self.seg_nodes_apply(interval, &mut |s, i| {
-            s.nodes[i].segs.retain(|&x| x != id)
-        }, 0);
-        Ok(())
+    // like seg_nodes_cond_visit, but counts segments stored on partially overlapping ancestors
+    // and clamps covered nodes to the query; sinks one interval per node, uncoalesced.
+    // Returns false once the sink asked to stop. Subtrees with no coverage (max_ovp == 0, nothing on
+    // ancestors) are pruned, so a query on an empty tree never gets past the root
+    fn union_visit(&self, seg: (f64, f64), include_boundary: bool, min_depth: usize, acc: usize, node_id: usize, sink: &mut dyn FnMut((f64, f64)) -> bool) -> bool {
+        #[cfg(test)]
+        UNION_VISITS.with(|visits| visits.set(visits.get() + 1));
+        let node = &self.nodes[node_id];
+        if acc + self.stats[node_id].max_ovp < min_depth {
+            return true;
+        }
+        if acc + self.stats[node_id].min_ovp >= min_depth {
+            let itv = (node.itv.0.max(seg.0), node.itv.1.min(seg.1));
+            // a node merely touching the query end leaves the point to the point leaf below it
+            if itv.0 < itv.1 || node.itv.0 == node.itv.1 {
+                let on_boundary = itv.0 == itv.1 && (itv.0 == seg.0 || itv.1 == seg.1);
+                return (!include_boundary && on_boundary) || sink(itv);
+            }
+        }
+        if self.is_leaf(node_id) {
+            return true;
+        }
+        let acc = acc + node.segs.len();
+        for child in [node_id.left(), node_id.right()] {
+            let itv = self.nodes[child].itv;
+            let reached = if include_boundary {
+                itv.0 <= seg.1 && seg.0 <= itv.1
+            } else {
+                itv.reaches(seg)
+            };
+            if reached && !self.union_visit(seg, include_boundary, min_depth, acc, child, sink) {
+                return false;
+            }
+        }
+        true
     }
 
-    pub fn get_union(&self, interval: (f64, f64)) -> Result<Union, InputError> {
-        interval.validate_inf()?;
-        let mut union = Union { intervals: Vec::new() };
-        self.seg_nodes_cond_visit(interval, &mut |s, i| {
-            let node = &s.nodes[i];
-            if s.stats[i].min_ovp > 0{
-                if union.intervals.is_empty() {
-                    union.intervals.push(node.itv);
-                } else {
-                    let last_itv = union.intervals.last_mut().unwrap();
-                    if last_itv.1 == node.itv.0 {
-                        last_itv.1 = node.itv.1;
-                    } else {
-                        union.intervals.push(node.itv);
-                    }
+    fn depth_union_visit(&self, seg: (f64, f64), acc: usize, node_id: usize, union: &mut DepthUnion) {
+        let node = &self.nodes[node_id];
+        let stats = &self.stats[node_id];
+        if stats.min_ovp == stats.max_ovp {
+            let itv = (node.itv.0.max(seg.0), node.itv.1.min(seg.1));
+            if itv.0 < itv.1 || (node.itv.0 == node.itv.1 && seg.contains(node.itv)) {
+                if acc + stats.min_ovp > 0 {
+                    union.push_coalesced(itv, acc + stats.min_ovp);
                 }
-                return false
+                return;
             }
-            return true;
-        }, 0);
-        Ok(union)
+        }
+        if self.is_leaf(node_id) {
+            return;
+        }
+        let acc = acc + node.segs.len();
+        for child in [node_id.left(), node_id.right()] {
+            if self.nodes[child].itv.reaches(seg) {
+                self.depth_union_visit(seg, acc, child, union);
+            }
+        }
     }
 
-    pub fn root_stats(&self) -> Stats {
-        self.stats[0]
+    // leftmost point >= from in a leaf whose depth (acc included) satisfies pred
+    fn first_leaf_point(&self, from: f64, pred: &dyn Fn(usize) -> bool, prune: &dyn Fn(usize, &Stats) -> bool, acc: usize, node_id: usize) -> Option<f64> {
+        let node = &self.nodes[node_id];
+        if node.itv.1 < from || prune(acc, &self.stats[node_id]) {
+            return None;
+        }
+        if self.is_leaf(node_id) {
+            let (lo, hi) = node.itv;
+            let reaches_from = if lo == hi { lo >= from } else { hi > from };
+            return (reaches_from && pred(acc + node.segs.len())).then_some(lo.max(from));
+        }
+        let acc = acc + node.segs.len();
+        self.first_leaf_point(from, pred, prune, acc, node_id.left())
+            .or_else(|| self.first_leaf_point(from, pred, prune, acc, node_id.right()))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // depth (acc included) of the leaf holding query; an endpoint belongs to its point leaf [v, v]
+    fn leaf_depth(&self, query: f64, acc: usize, node_id: usize) -> Option<usize> {
+        let node = &self.nodes[node_id];
+        if self.is_leaf(node_id) {
+            let (lo, hi) = node.itv;
+            let holds = if lo == hi { lo == query } else { lo < query && query < hi };
+            return holds.then_some(acc + node.segs.len());
+        }
+        let acc = acc + node.segs.len();
+        let (left, right) = (node_id.left(), node_id.right());
+        if query < self.nodes[left].itv.1 {
+            self.leaf_depth(query, acc, left)
+        } else if query > self.nodes[right].itv.0 {
+            self.leaf_depth(query, acc, right)
+        } else {
+            // on the shared boundary the point leaf is the last leaf on the left or the first on the right
+            self.leaf_depth(query, acc, left).or_else(|| self.leaf_depth(query, acc, right))
+        }
+    }
 
-    fn print_tree(tree: &SegmentTree) {
-        for (i, node) in tree.nodes.iter().enumerate() {
-            let stats = tree.stats[i];
-            println!("Node {}: itv=({},{}) segs={:?} stats=(length: {}, max_ovp: {})", i, node.itv.0, node.itv.1, node.segs, stats.length, stats.max_ovp);
+    // outermost covered node interval on the left (or right) side, pruning uncovered subtrees
+    fn outermost_covered(&self, rightmost: bool, node_id: usize) -> Option<(f64, f64)> {
+        let node = &self.nodes[node_id];
+        if self.stats[node_id].max_ovp == 0 {
+            return None;
+        }
+        if !node.segs.is_empty() {
+            return Some(node.itv);
+        }
+        if self.is_leaf(node_id) {
+            return None;
         }
+        let (first, second) = if rightmost { (node_id.right(), node_id.left()) } else { (node_id.left(), node_id.right()) };
+        self.outermost_covered(rightmost, first).or_else(|| self.outermost_covered(rightmost, second))
+    }
+
+    // (min, max) depth over leaves reached by seg; only nodes strictly inside it use their stats,
+    // since a node merely sharing a bound with seg may hold the point leaf at that bound
+    fn depth_range_visit(&self, seg: (f64, f64), acc: usize, node_id: usize) -> Option<(usize, usize)> {
+        let node = &self.nodes[node_id];
+        if seg.0 < node.itv.0 && node.itv.1 < seg.1 {
+            let stats = &self.stats[node_id];
+            return Some((acc + stats.min_ovp, acc + stats.max_ovp));
+        }
+        let acc = acc + node.segs.len();
+        if self.is_leaf(node_id) {
+            return Some((acc, acc));
+        }
+        [node_id.left(), node_id.right()].into_iter()
+            .filter(|&child| self.nodes[child].itv.reaches(seg))
+            .filter_map(|child| self.depth_range_visit(seg, acc, child))
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+    }
+
+    // pushes regions of the subtree where depth (acc from ancestors included) is at least k
+    fn collect_depth_at_least(&self, k: usize, acc: usize, node_id: usize, union: &mut Union) {
+        let stats = &self.stats[node_id];
+        if acc + stats.max_ovp < k {
+            return;
+        }
+        if acc + stats.min_ovp >= k {
+            union.push_coalesced(self.nodes[node_id].itv);
+            return;
+        }
+        let acc = acc + self.nodes[node_id].segs.len();
+        self.collect_depth_at_least(k, acc, node_id.left(), union);
+        self.collect_depth_at_least(k, acc, node_id.right(), union);
+    }
+
+    // leaves left to right: the incomplete last level first, then the leaves of the last complete level
+    fn leaves_in_order(&self) -> impl Iterator<Item = usize> {
+        let tree_size = self.nodes.len();
+        let compl_tree_size = (1usize << ((tree_size + 1).ilog2())) - 1;
+        (compl_tree_size..tree_size).chain((tree_size >> 1)..compl_tree_size)
+    }
+
+    #[inline]
+    fn is_leaf(&self, idx: usize) -> bool {
+        idx >= (self.nodes.len() >> 1)
+    }
+}
+
+impl fmt::Debug for SegmentTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.dump())
+    }
+}
+
+impl SegmentTree {
+
+    pub fn new(all_ends: Vec<f64>) -> Result<Self, InputError> {
+        Self::new_with_epsilon(all_ends, 0.0)
+    }
+
+    /// Endpoints within `epsilon` of the first endpoint of their run are merged into it. Segment
+    /// and query bounds within `epsilon` of an endpoint are snapped onto the nearest one, so a
+    /// merged-away value still addresses its representative.
+    pub fn new_with_epsilon(all_ends: Vec<f64>, epsilon: f64) -> Result<Self, InputError> {
+        all_ends.validate()?;
+        Self::validate_epsilon(epsilon)?;
+        let mut tree = Self::from_valid_ends(all_ends, Some(epsilon))?;
+        tree.epsilon = epsilon;
+        Ok(tree)
+    }
+
+    /// With `dedup` off, repeated endpoints are kept: each extra copy adds a zero-width open leaf
+    /// and another point leaf, i.e. 4 more nodes (with their stats and segment vectors), and
+    /// `endpoints()` reports every copy.
+    pub fn new_with_options(all_ends: Vec<f64>, dedup: bool) -> Result<Self, InputError> {
+        all_ends.validate()?;
+        Self::from_valid_ends(all_ends, dedup.then_some(0.0))
+    }
+
+    /// A tree over the single leaf `(-inf, +inf)` that gains endpoints as segments are added:
+    /// each `add_segment` with a finite end not yet in the tree rebuilds it, so insertion is O(n)
+    /// rather than O(log n). Meant for coarse trees with few distinct ends.
+    pub fn new_lazy() -> Self {
+        let mut tree = Self::with_ends(&[]).expect("a single-leaf tree always fits");
+        tree.lazy = true;
+        tree
+    }
+
+    pub fn builder() -> SegmentTreeBuilder {
+        SegmentTreeBuilder::default()
+    }
+
+    /// Skips sorting and deduplication; `all_ends` must already be strictly increasing.
+    pub fn new_sorted(mut all_ends: Vec<f64>) -> Result<Self, InputError> {
+        all_ends.validate()?;
+        Self::normalize_ends(&mut all_ends)?;
+        if all_ends.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(InputError::UnsortedEndpoints);
+        }
+        Self::with_ends(&all_ends)
+    }
+
+    pub fn from_segments(segments: &[((f64, f64), usize)]) -> Result<Self, InputError> {
+        let mut all_ends = Vec::with_capacity(segments.len() << 1);
+        for &(interval, _) in segments {
+            interval.validate()?;
+            all_ends.push(interval.0);
+            all_ends.push(interval.1);
+        }
+        let mut tree = Self::new(all_ends)?;
+        for &(interval, id) in segments {
+            tree.add_segment(interval, id)?;
+        }
+        Ok(tree)
+    }
+
+    /// The segment covers the half-open `(start, end]`: the point leaf at its end but not the one at
+    /// its start, so touching segments do not overlap. A point segment `(v, v)` covers just `v`.
+    /// All input checks run before the tree is touched, so an `Err` leaves it unchanged.
+    pub fn add_segment(&mut self, interval: (f64, f64), id: usize) -> Result<(), InputError> {
+        Self::validate_bounded(interval)?;
+        self.add_segment_unbounded(interval, id)
+    }
+
+    /// Replaces whatever interval `id` was last added with by `interval`, so repeating the call
+    /// is idempotent. Ids without a stored original interval are simply added.
+    pub fn upsert(&mut self, interval: (f64, f64), id: usize) -> Result<(), InputError> {
+        Self::validate_bounded(interval)?;
+        if let Some(old) = self.originals.get(&id).copied() {
+            self.remove_segment(old, id)?;
+        }
+        self.add_segment_unbounded(interval, id)
+    }
+
+    /// Like `add_segment`, but the interval may extend to ±infinity, covering the sentinel leaves.
+    pub fn add_segment_unbounded(&mut self, interval: (f64, f64), id: usize) -> Result<(), InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        if self.lazy {
+            self.insert_ends(interval)?;
+        }
+        self.seg_nodes_apply(interval.into(),
+            &mut |s, i| {
+                s.nodes[i].insert_seg(id);
+                s.n_slots += 1;
+            }, 0);
+        self.originals.insert(id, interval);
+        Ok(())
+    }
+
+    /// Stores the segment without updating any node stats, for bulk loads: stats-based queries
+    /// (`root_stats`, unions, gaps, ...) are stale until `recompute_stats` is called.
+    pub fn add_segment_deferred(&mut self, interval: (f64, f64), id: usize) -> Result<(), InputError> {
+        Self::validate_bounded(interval)?;
+        let interval = self.snap(interval);
+        if self.lazy {
+            self.insert_ends(interval)?;
+        }
+        let mut canonical = Vec::new();
+        self.seg_nodes_cond_visit(interval.into(), &mut |_, i| {
+            canonical.push(i);
+            false
+        }, 0);
+        self.n_slots += canonical.len();
+        for i in canonical {
+            self.nodes[i].insert_seg(id);
+        }
+        self.originals.insert(id, interval);
+        Ok(())
+    }
+
+    /// One bottom-up pass recomputing every node's stats, e.g. after `add_segment_deferred` calls.
+    pub fn recompute_stats(&mut self) {
+        for i in (0..self.nodes.len()).rev() {
+            self.update_stats(i);
+        }
+    }
+
+    pub fn remove_segment(&mut self, interval: (f64, f64), id: usize) -> Result<(), InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        self.seg_nodes_apply(interval.into(), &mut |s, i| {
+            s.n_slots -= s.nodes[i].remove_seg(id);
+        }, 0);
+        if self.originals.get(&id) == Some(&interval) {
+            self.originals.remove(&id);
+        }
+        Ok(())
+    }
+
+    /// `remove_segment` with the interval `id` was last added with; false if it has none stored.
+    pub fn remove(&mut self, id: usize) -> Result<bool, InputError> {
+        match self.originals.get(&id).copied() {
+            Some(interval) => {
+                self.remove_segment(interval, id)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// `remove_segment` for each pair, with all intervals validated first and the stats
+    /// recomputed once at the end.
+    pub fn remove_segments(&mut self, segments: &[((f64, f64), usize)]) -> Result<(), InputError> {
+        for &(interval, _) in segments {
+            interval.validate_inf()?;
+        }
+        for &(interval, id) in segments {
+            let interval = self.snap(interval);
+            let mut canonical = Vec::new();
+            self.seg_nodes_cond_visit(interval.into(), &mut |_, i| {
+                canonical.push(i);
+                false
+            }, 0);
+            for i in canonical {
+                self.n_slots -= self.nodes[i].remove_seg(id);
+            }
+            if self.originals.get(&id) == Some(&interval) {
+                self.originals.remove(&id);
+            }
+        }
+        self.recompute_stats();
+        Ok(())
+    }
+
+    pub fn relabel(&mut self, id: usize, new_id: usize) -> Result<(), InputError> {
+        if id == new_id {
+            return Ok(());
+        }
+        if self.originals.contains_key(&new_id) || self.nodes.iter().any(|node| node.has_seg(new_id)) {
+            return Err(InputError::DuplicateId);
+        }
+        if let Some(interval) = self.originals.remove(&id) {
+            self.originals.insert(new_id, interval);
+        }
+        // the count per node is unchanged, so are the stats
+        for node in self.nodes.iter_mut() {
+            for _ in 0..node.remove_seg(id) {
+                node.insert_seg(new_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Shifts every finite coordinate (node bounds and original intervals) by `delta`.
+    pub fn translate(&mut self, delta: f64) -> Result<(), InputError> {
+        delta.validate()?;
+        self.map_coords(|x| x + delta)
+    }
+
+    /// Multiplies every finite coordinate by `factor`, which must be positive to keep the order.
+    pub fn scale(&mut self, factor: f64) -> Result<(), InputError> {
+        factor.validate()?;
+        if factor <= 0.0 {
+            return Err(InputError::NonPositiveFactor);
+        }
+        self.map_coords(|x| x * factor)?;
+        self.epsilon *= factor;
+        Ok(())
+    }
+
+    /// Removes every segment, keeping the endpoint structure.
+    pub fn clear(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.segs.clear();
+        }
+        self.n_slots = 0;
+        self.stats.fill(Stats::default());
+        self.originals.clear();
+    }
+
+    /// Drops all coverage outside `bounds`: the finite bounds become endpoints and the segments
+    /// are re-added from their original intervals clamped to `bounds`, dropping those that share no
+    /// point with it. Both are taken half-open `(start, end]` like stored segments, so an interval
+    /// that only touches `bounds`, `(0, 2)` against `(2, 5)`, is dropped rather than kept as a point.
+    /// Storage that no original interval accounts for is lost.
+    pub fn clip(&mut self, bounds: (f64, f64)) -> Result<(), InputError> {
+        bounds.validate_inf()?;
+        let bounds = self.snap(bounds);
+        self.insert_ends(bounds)?;
+        let originals = std::mem::take(&mut self.originals);
+        self.clear();
+        for (id, itv) in originals {
+            if bounds.shares_point(itv) {
+                self.add_segment_unbounded((itv.0.max(bounds.0), itv.1.min(bounds.1)), id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds every segment of `other`, which must be built over the same endpoints. Ids are kept,
+    /// so an id stored in both trees is a `DuplicateId` error and leaves `self` unchanged.
+    pub fn merge(&mut self, other: &Self) -> Result<(), InputError> {
+        if self.endpoints() != other.endpoints() {
+            return Err(InputError::EndpointsMismatch);
+        }
+        let ids: HashSet<usize> = self.nodes.iter()
+            .flat_map(|node| node.segs.iter().copied())
+            .chain(self.originals.keys().copied())
+            .collect();
+        let collides = other.originals.keys().any(|id| ids.contains(id))
+            || other.nodes.iter().any(|node| node.segs.iter().any(|id| ids.contains(id)));
+        if collides {
+            return Err(InputError::DuplicateId);
+        }
+        for (node, other_node) in self.nodes.iter_mut().zip(other.nodes.iter()) {
+            for &id in other_node.segs.iter() {
+                node.insert_seg(id);
+                self.n_slots += 1;
+            }
+        }
+        self.originals.extend(other.originals.iter().map(|(&id, &interval)| (id, interval)));
+        self.recompute_stats();
+        Ok(())
+    }
+
+    /// |A ∩ B| / |A ∪ B| of the covered lengths; 1.0 when neither tree covers anything.
+    /// `ContainsInfinite` if either tree has unbounded coverage, where the ratio is undefined.
+    pub fn jaccard(&self, other: &Self) -> Result<f64, InputError> {
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        let (a, b) = (self.get_union(all, false, true)?, other.get_union(all, false, true)?);
+        if a.length().is_infinite() || b.length().is_infinite() {
+            return Err(InputError::ContainsInfinite);
+        }
+        let union_len = a.union(&b).length();
+        if union_len == 0.0 {
+            return Ok(1.0);
+        }
+        Ok(a.intersection(&b).length() / union_len)
+    }
+
+    /// Whether the two coverages share no region of positive length. Like `jaccard` this compares
+    /// covered length, so sharing a single point is fine: touching segments, and point segments
+    /// `(v, v)` even where the other tree covers `v`, never make the trees intersect.
+    pub fn is_disjoint(&self, other: &Self) -> Result<bool, InputError> {
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        let (a, b) = (self.get_union(all, false, true)?, other.get_union(all, false, true)?);
+        // `intersection` only keeps positive-length pieces
+        Ok(a.intersection(&b).intervals.is_empty())
+    }
+
+    /// Regions covered by exactly one of the two trees.
+    pub fn symmetric_difference(&self, other: &Self) -> Result<Union, InputError> {
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        let (a, b) = (self.get_union(all, false, true)?, other.get_union(all, false, true)?);
+        Ok(a.difference(&b).union(&b.difference(&a)))
+    }
+
+    /// A new tree over the endpoints inside `interval` (plus its finite bounds), holding every
+    /// segment whose original interval reaches the query, clamped to it.
+    pub fn subtree(&self, interval: (f64, f64)) -> Result<Self, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        let mut ends: Vec<f64> = self.endpoints().into_iter()
+            .filter(|&end| interval.0 <= end && end <= interval.1)
+            .collect();
+        ends.extend([interval.0, interval.1].into_iter().filter(|end| end.is_finite()));
+        let mut tree = Self::from_valid_ends(ends, Some(0.0))?;
+        tree.epsilon = self.epsilon;
+        for (&id, &itv) in &self.originals {
+            if interval.reaches(itv) {
+                tree.add_segment_unbounded((itv.0.max(interval.0), itv.1.min(interval.1)), id)?;
+            }
+        }
+        Ok(tree)
+    }
+
+    pub fn snapshot(&self) -> TreeSnapshot {
+        TreeSnapshot { nodes: self.nodes.clone(), stats: self.stats.clone(), originals: self.originals.clone() }
+    }
+
+    pub fn restore(&mut self, snap: TreeSnapshot) {
+        self.nodes = snap.nodes;
+        self.stats = snap.stats;
+        self.originals = snap.originals;
+        self.n_slots = self.nodes.iter().map(|node| node.segs.len()).sum();
+    }
+
+    /// Enough for `from_state` to rebuild an equal tree; originals are sorted by id.
+    pub fn to_state(&self) -> TreeState {
+        let storage = self.nodes.iter().enumerate()
+            .flat_map(|(i, node)| node.segs.iter().map(move |&id| (i, id)))
+            .collect();
+        let mut originals: Vec<(usize, (f64, f64))> = self.originals.iter().map(|(&id, &itv)| (id, itv)).collect();
+        originals.sort_unstable_by_key(|&(id, _)| id);
+        (self.endpoints(), storage, originals, self.lazy, self.epsilon)
+    }
+
+    pub fn from_state(all_ends: Vec<f64>, storage: Vec<(usize, usize)>, originals: Vec<(usize, (f64, f64))>, lazy: bool, epsilon: f64) -> Result<Self, InputError> {
+        Self::validate_epsilon(epsilon)?;
+        let mut tree = Self::new_with_options(all_ends, false)?;
+        tree.lazy = lazy;
+        tree.epsilon = epsilon;
+        for (node_id, id) in storage {
+            tree.nodes.get_mut(node_id).ok_or(InputError::InvalidState)?.insert_seg(id);
+            tree.n_slots += 1;
+        }
+        for (id, interval) in originals {
+            interval.validate_inf()?;
+            tree.originals.insert(id, interval);
+        }
+        tree.recompute_stats();
+        Ok(tree)
+    }
+
+    /// Covered regions inside `interval`. With `include_boundary` the query is closed, so a covered
+    /// point leaf `[a, a]` or `[b, b]` at the query ends is reported (as a degenerate `(a, a)` interval
+    /// when isolated); otherwise the query is open and those point leaves are left out.
+    /// Without `coalesce` the raw per-node intervals of the canonical decomposition are returned.
+    pub fn get_union(&self, interval: (f64, f64), include_boundary: bool, coalesce: bool) -> Result<Union, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        let mut union = Union { intervals: Vec::new() };
+        self.union_visit(interval, include_boundary, 1, 0, 0, &mut |itv| { union.intervals.push(itv); true });
+        if coalesce {
+            union.coalesce();
+        }
+        Ok(union)
+    }
+
+    /// Like `get_union`, but only regions where at least `min_depth` segments overlap.
+    pub fn get_union_min_depth(&self, interval: (f64, f64), min_depth: usize) -> Result<Union, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        let mut union = Union { intervals: Vec::new() };
+        self.union_visit(interval, false, min_depth, 0, 0, &mut |itv| { union.intervals.push(itv); true });
+        union.coalesce();
+        Ok(union)
+    }
+
+    /// `get_union(interval, false, true)` for each query; all queries are validated before any runs.
+    pub fn get_unions(&self, intervals: &[(f64, f64)]) -> Result<Vec<Union>, InputError> {
+        for interval in intervals {
+            interval.validate_inf()?;
+        }
+        intervals.iter().map(|&interval| self.get_union(interval, false, true)).collect()
+    }
+
+    /// Streams the coalesced covered intervals of `get_union(interval, false, true)` to `f`, left to right.
+    /// Traversal stops as soon as `f` returns false.
+    pub fn visit_union<F: FnMut((f64, f64)) -> bool>(&self, interval: (f64, f64), mut f: F) -> Result<(), InputError> {
+        for itv in self.union_iter(interval)? {
+            if !f(itv) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lazily yields the coalesced covered intervals of `get_union(interval, false, true)`, left to right,
+    /// without collecting them; the traversal stack holds O(depth) nodes.
+    pub fn union_iter<'a>(&'a self, interval: (f64, f64)) -> Result<impl Iterator<Item = (f64, f64)> + 'a, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        Ok(UnionIter { tree: self, seg: interval, stack: vec![(0, 0)], pending: None })
+    }
+
+    pub fn get_union_with_depth(&self, interval: (f64, f64)) -> Result<DepthUnion, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        let mut union = DepthUnion { intervals: Vec::new() };
+        self.depth_union_visit(interval, 0, 0, &mut union);
+        Ok(union)
+    }
+
+    /// The whole piecewise-constant depth function over `interval`: maximal `(start, end, depth)`
+    /// runs left to right, uncovered stretches included with depth 0.
+    pub fn coverage_rle(&self, interval: (f64, f64)) -> Result<Vec<(f64, f64, usize)>, InputError> {
+        let interval = self.snap(interval);
+        let runs = self.get_union_with_depth(interval)?;
+        if interval.0 == interval.1 {
+            return Ok(vec![(interval.0, interval.1, self.leaf_depth(interval.0, 0, 0).unwrap_or(0))]);
+        }
+        let mut rle = DepthUnion { intervals: Vec::new() };
+        let mut cursor = interval.0;
+        for &(start, end, depth) in &runs.intervals {
+            if start > cursor {
+                rle.push_coalesced((cursor, start), 0);
+            }
+            rle.push_coalesced((start, end), depth);
+            cursor = end;
+        }
+        if interval.1 > cursor {
+            rle.push_coalesced((cursor, interval.1), 0);
+        }
+        Ok(rle.intervals)
+    }
+
+    /// Sweep-line style `(coordinate, delta)` events where the overlap depth inside `interval` changes;
+    /// a running sum of the deltas gives the depth right of each coordinate and ends at 0.
+    pub fn depth_transitions(&self, interval: (f64, f64)) -> Result<Vec<(f64, i64)>, InputError> {
+        let runs = self.get_union_with_depth(interval)?;
+        let mut events = Vec::new();
+        let mut depth = 0;
+        let mut run_end = f64::NAN;
+        for &(start, end, run_depth) in &runs.intervals {
+            let run_depth = run_depth as i64;
+            if depth != 0 && run_end != start {
+                events.push((run_end, -depth));
+                depth = 0;
+            }
+            if run_depth != depth {
+                events.push((start, run_depth - depth));
+                depth = run_depth;
+            }
+            run_end = end;
+        }
+        if depth != 0 {
+            events.push((run_end, -depth));
+        }
+        Ok(events)
+    }
+
+    /// Sorted coordinates of `depth_transitions`, without the deltas.
+    pub fn breakpoints(&self, interval: (f64, f64)) -> Result<Vec<f64>, InputError> {
+        let mut coords: Vec<f64> = self.depth_transitions(interval)?.into_iter().map(|(coord, _)| coord).collect();
+        coords.dedup();
+        Ok(coords)
+    }
+
+    /// Integral of the overlap depth over `interval`: the total length of the segments' parts inside
+    /// it, counting overlaps.
+    pub fn coverage_integral(&self, interval: (f64, f64)) -> Result<f64, InputError> {
+        let runs = self.get_union_with_depth(interval)?;
+        Ok(runs.intervals.iter().map(|&(start, end, depth)| (end - start) * depth as f64).sum())
+    }
+
+    /// Maximal uncovered intervals inside `interval`, left to right.
+    pub fn get_gaps(&self, interval: (f64, f64)) -> Result<Union, InputError> {
+        let interval = self.snap(interval);
+        let covered = self.get_union(interval, false, true)?;
+        let mut gaps = Union { intervals: Vec::new() };
+        let mut start = interval.0;
+        for &(lo, hi) in covered.intervals.iter() {
+            if lo > start {
+                gaps.intervals.push((start, lo));
+            }
+            start = start.max(hi);
+        }
+        if interval.1 > start {
+            gaps.intervals.push((start, interval.1));
+        }
+        Ok(gaps)
+    }
+
+    /// Lengths of the finite gaps from `get_gaps`, left to right.
+    pub fn gap_lengths(&self, interval: (f64, f64)) -> Result<Vec<f64>, InputError> {
+        Ok(self.get_gaps(interval)?.intervals.iter()
+            .map(|itv| itv.1 - itv.0)
+            .filter(|len| len.is_finite())
+            .collect())
+    }
+
+    /// Covered length inside each of `bins` equal-width buckets spanning the finite `interval`.
+    /// A `bins` count that cannot be allocated is `TooLarge` rather than an abort.
+    pub fn coverage_profile(&self, interval: (f64, f64), bins: usize) -> Result<Vec<f64>, InputError> {
+        interval.validate()?;
+        let interval = self.snap(interval);
+        if bins == 0 {
+            return Err(InputError::ZeroBins);
+        }
+        let width = (interval.1 - interval.0) / bins as f64;
+        let bin_start = |k: usize| if k == bins { interval.1 } else { interval.0 + k as f64 * width };
+        let mut profile = Vec::new();
+        profile.try_reserve_exact(bins).map_err(|_| InputError::TooLarge)?;
+        profile.resize(bins, 0.0);
+        for &(lo, hi) in &self.get_union(interval, false, true)?.intervals {
+            let first = (((lo - interval.0) / width) as usize).min(bins - 1);
+            for (k, covered) in profile.iter_mut().enumerate().skip(first) {
+                if bin_start(k) >= hi {
+                    break;
+                }
+                *covered += (hi.min(bin_start(k + 1)) - lo.max(bin_start(k))).max(0.0);
+            }
+        }
+        Ok(profile)
+    }
+
+    /// Widest covered interval inside the query, the leftmost one on ties.
+    pub fn longest_covered(&self, interval: (f64, f64)) -> Result<Option<(f64, f64)>, InputError> {
+        let union = self.get_union(interval, false, true)?;
+        Ok(union.intervals.into_iter().fold(None, |best, itv| match best {
+            Some(b) if b.1 - b.0 >= itv.1 - itv.0 => Some(b),
+            _ => Some(itv),
+        }))
+    }
+
+    /// Number of maximal covered intervals inside `interval`, counted without collecting them.
+    pub fn component_count(&self, interval: (f64, f64)) -> Result<usize, InputError> {
+        let mut count = 0;
+        self.visit_union(interval, |_| {
+            count += 1;
+            true
+        })?;
+        Ok(count)
+    }
+
+    /// Total covered length in `(-inf, point)`; infinite if an unbounded segment reaches `-inf`.
+    pub fn covered_length_before(&self, point: f64) -> Result<f64, InputError> {
+        Ok(self.get_union((f64::NEG_INFINITY, point), false, true)?.length())
+    }
+
+    /// Covered intervals left and right of `at`, an interval straddling `at` being split there.
+    pub fn coverage_split(&self, at: f64) -> Result<(Union, Union), InputError> {
+        at.validate_inf()?;
+        let left = self.get_union((f64::NEG_INFINITY, at), false, true)?;
+        let right = self.get_union((at, f64::INFINITY), false, true)?;
+        Ok((left, right))
+    }
+
+    /// Ids of the segments overlapping `interval`, taken as an open interval: a segment counts
+    /// when it covers a point strictly inside, so one that only touches the query does not, and
+    /// a point query `(v, v)` is empty. `depth_at_point` answers what covers a single point.
+    pub fn segments_in(&self, interval: (f64, f64)) -> Result<Vec<usize>, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        let mut ids = Vec::new();
+        if interval.0 < interval.1 && self.nodes[0].itv.overlaps(interval) {
+            self.overlap_nodes_visit(interval, &mut |s, i| {
+                ids.extend_from_slice(&s.nodes[i].segs);
+            }, 0);
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// The interval last passed to `add_segment` (or a variant) for `id`, until it is removed.
+    pub fn segment_interval(&self, id: usize) -> Option<(f64, f64)> {
+        self.originals.get(&id).copied()
+    }
+
+    /// Every id with the length of its original interval, longest first (ties by id).
+    pub fn ids_by_length(&self) -> Vec<(usize, f64)> {
+        let mut lengths: Vec<(usize, f64)> = self.originals.iter().map(|(&id, itv)| (id, itv.1 - itv.0)).collect();
+        lengths.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        lengths
+    }
+
+    /// Number of unordered id pairs whose original intervals share a point, by a sweep over the
+    /// intervals in start order. Intervals are half-open `(start, end]` like the stored segments,
+    /// so touching ends do not count, while a point segment `(v, v)` meets intervals ending at `v`.
+    pub fn overlapping_pair_count(&self) -> usize {
+        let mut itvs: Vec<(f64, f64)> = self.originals.values().copied().collect();
+        itvs.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        let mut ends: Vec<f64> = itvs.iter().map(|itv| itv.1).collect();
+        ends.sort_by(f64::total_cmp);
+        // active ends are held as their positions in the sorted ends, which order like the values
+        let mut active = BinaryHeap::new();
+        let mut pairs = 0;
+        // at equal starts the point segment sorts first, since (v, b] does not hold v
+        for &(start, end) in &itvs {
+            let n_ended = if start == end {
+                ends.partition_point(|&e| e < start)
+            } else {
+                ends.partition_point(|&e| e <= start)
+            };
+            while active.peek().is_some_and(|&Reverse(pos)| pos < n_ended) {
+                active.pop();
+            }
+            pairs += active.len();
+            active.push(Reverse(ends.partition_point(|&e| e < end)));
+        }
+        pairs
+    }
+
+    /// A largest set of ids whose original intervals pairwise share no point, picked greedily by
+    /// earliest end. Intervals are half-open `(start, end]` as in `overlapping_pair_count`: touching
+    /// is fine, but a point segment `(v, v)` clashes with an interval ending at `v`. Ids come in the
+    /// order they were picked.
+    pub fn max_non_overlapping(&self) -> Vec<usize> {
+        let mut by_end: Vec<(usize, (f64, f64))> = self.originals.iter().map(|(&id, &itv)| (id, itv)).collect();
+        by_end.sort_by(|a, b| a.1 .1.total_cmp(&b.1 .1).then(a.1 .0.total_cmp(&b.1 .0)).then(a.0.cmp(&b.0)));
+        let mut picked = Vec::new();
+        // ends only grow, so clearing the last pick clears all of them
+        let mut last = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for (id, itv) in by_end {
+            if picked.is_empty() || !itv.shares_point(last) {
+                picked.push(id);
+                last = itv;
+            }
+        }
+        picked
+    }
+
+    /// Ids whose original interval (as last passed to `add_segment`) lies inside `interval`.
+    pub fn contained_in(&self, interval: (f64, f64)) -> Result<Vec<usize>, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        let mut ids: Vec<usize> = self.originals.iter()
+            .filter(|&(_, &itv)| interval.contains(itv))
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    pub fn distinct_overlap_count(&self, interval: (f64, f64)) -> Result<usize, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        let mut ids = HashSet::new();
+        if self.nodes[0].itv.overlaps(interval) {
+            self.overlap_nodes_visit(interval, &mut |s, i| {
+                ids.extend(s.nodes[i].segs.iter().copied());
+            }, 0);
+        }
+        Ok(ids.len())
+    }
+
+    /// Visits nodes whose interval lies inside `interval` with their stored segment ids;
+    /// `f` returns whether to descend into the node's children.
+    pub fn visit_cover<F: FnMut((f64, f64), &[usize]) -> bool>(&self, interval: (f64, f64), mut f: F) -> Result<(), InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        self.seg_nodes_cond_visit(interval.into(), &mut |s, i| {
+            let node = &s.nodes[i];
+            f(node.itv, &node.segs)
+        }, 0);
+        Ok(())
+    }
+
+    /// Whether some segment overlaps the open `interval`, as in `segments_in`: touching the query
+    /// does not count and a point query is always `false`.
+    pub fn overlaps_any(&self, interval: (f64, f64)) -> Result<bool, InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        Ok(interval.0 < interval.1 && self.any_overlap(interval, 0))
+    }
+
+    /// Whether segments `a` and `b` share some covered point. Canonical node intervals are either
+    /// nested or disjoint, so this holds iff a node storing `b` is, or lies below, a node storing `a`
+    /// (or vice versa).
+    pub fn ids_overlap(&self, a: usize, b: usize) -> bool {
+        let stored_on_path = |id: usize, mut node_id: usize| loop {
+            if self.nodes[node_id].has_seg(id) {
+                return true;
+            }
+            if node_id == 0 {
+                return false;
+            }
+            node_id = (node_id - 1) >> 1;
+        };
+        (0..self.nodes.len()).any(|i| {
+            let node = &self.nodes[i];
+            (node.has_seg(b) && stored_on_path(a, i)) || (node.has_seg(a) && stored_on_path(b, i))
+        })
+    }
+
+    /// Infimum of the points `>= from` covered by no segment, `None` if everything rightward is
+    /// covered up to `+inf`. For an open leaf this is its left bound, which may itself be covered:
+    /// with `(1, 3)` stored, `first_uncovered(2.0)` is `3.0`, the end segments still hold.
+    pub fn first_uncovered(&self, from: f64) -> Result<Option<f64>, InputError> {
+        from.validate_inf()?;
+        let from = self.snap_point(from);
+        Ok(self.first_leaf_point(from, &|depth| depth == 0, &|acc, stats| acc + stats.min_ovp > 0, 0, 0))
+    }
+
+    /// Infimum of the points `>= from` covered by some segment, `None` if nothing is covered
+    /// rightward. For an open leaf this is its left bound, which may itself be uncovered: with
+    /// `(5, 6)` stored, `first_covered(0.0)` is `5.0`, the start segments leave out.
+    pub fn first_covered(&self, from: f64) -> Result<Option<f64>, InputError> {
+        from.validate_inf()?;
+        let from = self.snap_point(from);
+        Ok(self.first_leaf_point(from, &|depth| depth > 0, &|acc, stats| acc + stats.max_ovp == 0, 0, 0))
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        let segs_bytes: usize = self.nodes.iter()
+            .map(|node| node.segs.capacity() * std::mem::size_of::<usize>())
+            .sum();
+        self.nodes.capacity() * std::mem::size_of::<Node>()
+            + self.stats.capacity() * std::mem::size_of::<Stats>()
+            + self.originals.capacity() * std::mem::size_of::<(usize, (f64, f64))>()
+            + segs_bytes
+    }
+
+    /// Number of (node, id) storage slots, i.e. how many canonical nodes the segments are split into.
+    pub fn storage_count(&self) -> usize {
+        self.n_slots
+    }
+
+    /// No segment is stored, in O(1). A tree with endpoints but no segments is empty.
+    pub fn is_empty(&self) -> bool {
+        self.n_slots == 0
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Edges on the longest root-to-leaf path. The last level fills from the left, so that is the
+    /// leftmost path.
+    pub fn height(&self) -> usize {
+        let (mut node_id, mut height) = (0, 0);
+        while !self.is_leaf(node_id) {
+            node_id = node_id.left();
+            height += 1;
+        }
+        height
+    }
+
+    /// Structural self-check for debugging: every internal node spans exactly its two children,
+    /// the leaves tile the line in order with point leaves at odd positions, node ids are sorted
+    /// and counted, and the stats equal a fresh recompute (so it fails while
+    /// `add_segment_deferred` stats are pending). The error names the first violation found.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        for i in 0..(self.nodes.len() >> 1) {
+            let (itv, left, right) = (self.nodes[i].itv, self.nodes[i.left()].itv, self.nodes[i.right()].itv);
+            if itv != (left.0, right.1) {
+                return Err(format!("node {i} spans {itv:?}, its children {left:?} and {right:?}"));
+            }
+        }
+        let mut prev_end = f64::NEG_INFINITY;
+        for (ord, i) in self.leaves_in_order().enumerate() {
+            let (lo, hi) = self.nodes[i].itv;
+            if lo != prev_end || hi < lo || (ord & 1 == 1 && lo != hi) {
+                return Err(format!("leaf {i} at position {ord} is {:?} after end {prev_end}", (lo, hi)));
+            }
+            prev_end = hi;
+        }
+        if prev_end != f64::INFINITY {
+            return Err(format!("leaves end at {prev_end}"));
+        }
+        if let Some(i) = self.nodes.iter().position(|node| !node.segs.windows(2).all(|w| w[0] <= w[1])) {
+            return Err(format!("node {i} ids are unsorted"));
+        }
+        let n_slots: usize = self.nodes.iter().map(|node| node.segs.len()).sum();
+        if n_slots != self.n_slots {
+            return Err(format!("{n_slots} stored ids, {} counted", self.n_slots));
+        }
+        // bottom-up induction: each node agreeing with its children means all agree with a recompute
+        if let Some(i) = (0..self.nodes.len()).find(|&i| self.stats[i] != self.fresh_stats(i)) {
+            return Err(format!("node {i} stats differ from a recompute"));
+        }
+        Ok(())
+    }
+
+    /// Node interval starts, ends and stored segment counts, in node index order.
+    pub fn to_arrays(&self) -> (Vec<f64>, Vec<f64>, Vec<usize>) {
+        let starts = self.nodes.iter().map(|node| node.itv.0).collect();
+        let ends = self.nodes.iter().map(|node| node.itv.1).collect();
+        let counts = self.nodes.iter().map(|node| node.segs.len()).collect();
+        (starts, ends, counts)
+    }
+
+    /// Reserves room for `per_node` more ids on every node, avoiding regrowth during dense insertion.
+    pub fn reserve_segments(&mut self, per_node: usize) {
+        for node in self.nodes.iter_mut() {
+            node.segs.reserve(per_node);
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.segs.shrink_to_fit();
+        }
+        self.nodes.shrink_to_fit();
+        self.stats.shrink_to_fit();
+        self.originals.shrink_to_fit();
+    }
+
+    pub fn endpoints(&self) -> Vec<f64> {
+        self.leaves_in_order()
+            .skip(1)
+            .step_by(2)
+            .map(|i| self.nodes[i].itv.0)
+            .collect()
+    }
+
+    pub fn is_endpoint(&self, point: f64) -> bool {
+        let point = self.snap_point(point);
+        let ends = self.endpoints();
+        ends.get(ends.partition_point(|&end| end < point)) == Some(&point)
+    }
+
+    /// Largest endpoint `<= point` and smallest endpoint `>= point`; both are the endpoint itself
+    /// when `point` snaps onto it, as in `is_endpoint`.
+    pub fn surrounding_endpoints(&self, point: f64) -> (Option<f64>, Option<f64>) {
+        if point.is_nan() {
+            return (None, None);
+        }
+        let point = self.snap_point(point);
+        let ends = self.endpoints();
+        let below = ends.partition_point(|&end| end <= point);
+        let above = ends.partition_point(|&end| end < point);
+        (below.checked_sub(1).map(|k| ends[k]), ends.get(above).copied())
+    }
+
+    /// Number of segments covering `query`, read off the root-to-leaf path. Segments are half-open
+    /// `(start, end]`, so one ending at `query` counts and one starting there does not.
+    pub fn depth_at_point(&self, query: f64) -> Result<usize, InputError> {
+        query.validate()?;
+        let query = self.snap_point(query);
+        Ok(self.leaf_depth(query, 0, 0).unwrap_or(0))
+    }
+
+    /// Minimum and maximum overlap depth over the leaves the query reaches, in one traversal.
+    pub fn depth_range(&self, interval: (f64, f64)) -> Result<(usize, usize), InputError> {
+        interval.validate_inf()?;
+        let interval = self.snap(interval);
+        Ok(self.depth_range_visit(interval, 0, 0).unwrap_or((0, 0)))
+    }
+
+    /// Each endpoint with the overlap depth of its point leaf `[v, v]`.
+    pub fn endpoint_depths(&self) -> Vec<(f64, usize)> {
+        let mut depths = Vec::with_capacity(self.nodes.len() >> 2);
+        let mut leaf_ord = 0;
+        self.leaves_depth_visit(&mut |s, i, depth| {
+            if leaf_ord & 1 == 1 {
+                depths.push((s.nodes[i].itv.0, depth));
+            }
+            leaf_ord += 1;
+        }, 0, 0);
+        depths
+    }
+
+    /// Fraction of endpoints lying in the closure of the covered region (covered themselves, or
+    /// bounding a covered leaf). Low values flag over-subdivided trees; 0 for a tree without endpoints.
+    pub fn endpoint_utilization(&self) -> f64 {
+        let mut leaf_depths = Vec::with_capacity(self.nodes.len().div_ceil(2));
+        self.leaves_depth_visit(&mut |_, _, depth| leaf_depths.push(depth), 0, 0);
+        let n_ends = leaf_depths.len() >> 1;
+        if n_ends == 0 {
+            return 0.0;
+        }
+        let used = leaf_depths.windows(3).step_by(2).filter(|around| around.iter().any(|&d| d > 0)).count();
+        used as f64 / n_ends as f64
+    }
+
+    /// Total length covered by at least one segment over `(-inf, +inf)`, i.e. `root_stats().length`;
+    /// infinite once an unbounded segment is stored.
+    pub fn covered_length(&self) -> f64 {
+        self.stats[0].length
+    }
+
+    /// Smallest start and largest end of all covered regions, `None` if nothing is covered.
+    pub fn coverage_bounds(&self) -> Option<(f64, f64)> {
+        let left = self.outermost_covered(false, 0)?;
+        let right = self.outermost_covered(true, 0)?;
+        Some((left.0, right.1))
+    }
+
+    pub fn root_stats(&self) -> Stats {
+        self.stats[0]
+    }
+
+    /// Interval and stats of every node, in node index order (root first).
+    pub fn all_stats(&self) -> Vec<((f64, f64), Stats)> {
+        self.nodes.iter().zip(&self.stats).map(|(node, stats)| (node.itv, *stats)).collect()
+    }
+
+    pub fn global_min_overlap(&self) -> usize {
+        self.stats[0].min_ovp
+    }
+
+    pub fn global_max_overlap(&self) -> usize {
+        self.stats[0].max_ovp
+    }
+
+    /// Whether every point of the real line is covered, sentinel leaves included. Only segments
+    /// added with `add_segment_unbounded` reach `±inf`, so finite segments alone never make this true.
+    pub fn is_line_covered(&self) -> bool {
+        self.stats[0].min_ovp > 0
+    }
+
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let stats = self.stats[i];
+            writeln!(out, "Node {}: itv=({},{}) segs={:?} stats=(length: {}, max_ovp: {}, min_ovp: {})",
+                i, node.itv.0, node.itv.1, node.segs, stats.length, stats.max_ovp, stats.min_ovp).unwrap();
+        }
+        out
+    }
+
+    /// Finite length at each overlap depth, index = depth. Depths are read off the leaves rather than
+    /// `root_stats`, so stale stats after `add_segment_deferred` cannot undersize it.
+    pub fn depth_histogram(&self) -> Vec<f64> {
+        let mut hist = vec![0.0; self.root_stats().max_ovp + 1];
+        self.leaves_depth_visit(&mut |s, i, depth| {
+            let itv = s.nodes[i].itv;
+            if depth >= hist.len() {
+                hist.resize(depth + 1, 0.0);
+            }
+            if itv.0.is_finite() && itv.1.is_finite() {
+                hist[depth] += itv.1 - itv.0;
+            }
+        }, 0, 0);
+        hist
+    }
+
+    /// Regions where the overlap equals the global maximum; empty when no segment is stored.
+    pub fn peak_intervals(&self) -> Union {
+        match self.root_stats().max_ovp {
+            0 => Union { intervals: Vec::new() },
+            max_ovp => self.regions_at_least(max_ovp),
+        }
+    }
+
+    /// Coalesced regions where at least `k` segments overlap.
+    pub fn regions_at_least(&self, k: usize) -> Union {
+        let mut union = Union { intervals: Vec::new() };
+        self.collect_depth_at_least(k, 0, 0, &mut union);
+        union
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print_tree(tree: &SegmentTree) {
+        print!("{}", tree.dump());
     }
 
     fn count_leaves(tree: &SegmentTree) -> usize {
-        return (0..tree.nodes.len())
-                .filter(|&j| tree.is_leaf(j))
-                .count();
+        (0..tree.nodes.len())
+            .filter(|&j| tree.is_leaf(j))
+            .count()
+    }
+
+    #[test]
+    fn test_empty() {
+        let tree = SegmentTree::new(vec![]).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 0);
+        assert_eq!(tree.root_stats().length, 0.0);
+        assert_eq!(tree.nodes.len(), 1);
+    }
+
+    #[test]
+    fn test_no_segments() {
+        let tree = SegmentTree::new(vec![1.0, 2.0]).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 0);
+        assert_eq!(tree.root_stats().length, 0.0);
+    }
+
+    #[test]
+    fn test_n_nodes(){
+        for i in 0..32 {
+            let ends: Vec<f64> = (0..i).map(|x| x as f64).collect();
+            let tree = SegmentTree::new(ends).unwrap();
+            assert_eq!(tree.nodes.len(), (i << 2) + 1);
+        }
+    }
+
+    #[test]
+    fn test_n_leaves(){
+        for i in 0..32 {
+            let ends: Vec<f64> = (0..i).map(|x| x as f64).collect();
+            println!("ends: {:?}", ends);
+            let tree = SegmentTree::new(ends).unwrap();
+            print_tree(&tree);
+            let n_leaves = count_leaves(&tree);
+            println!("{}: {}", i, n_leaves);
+            assert_eq!(n_leaves, (i<<1)+1);
+        }
+    }
+
+    #[test]
+    fn test_add_segment() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0]).unwrap();
+        print_tree(&tree);
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        print_tree(&tree);
+        assert_eq!(tree.root_stats().max_ovp, 1);
+        assert_eq!(tree.root_stats().length, 1.0);
+    }
+    
+    #[test]
+    fn test_remove_segment() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0]).unwrap();
+        print_tree(&tree);
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        print_tree(&tree);
+        tree.remove_segment((1.0, 2.0), 0).unwrap();
+        print_tree(&tree);
+        assert_eq!(tree.root_stats().max_ovp, 0);
+        assert_eq!(tree.root_stats().length, 0.0);
+    }
+
+    #[test]
+    fn test_overlap() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 2.5, 3.0]).unwrap();
+        tree.add_segment((1.0, 2.5), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 2);
+        assert_eq!(tree.root_stats().length, 2.0);
+    }
+
+    #[test]
+    fn test_union() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 2.5, 3.0, 5.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment((2.5, 3.0), 1).unwrap();
+        tree.add_segment((3.0, 5.0), 2).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 1);
+        assert_eq!(tree.root_stats().length, 3.5);
+        print_tree(&tree);
+        let _union = tree.get_union((f64::NEG_INFINITY, f64::INFINITY), false, true).unwrap();
+        assert_eq!(_union.intervals, vec![(1.0, 2.0), (2.5, 5.0)]);
+        assert!(_union.contains_point(1.5));
+        assert!(!_union.contains_point(2.3));
+        assert!(_union.contains_point(4.0));
+        assert!(!_union.contains_point(f64::INFINITY));
+        assert!(!_union.contains_point(f64::NAN));
+    }
+
+    #[test]
+    fn test_peak_intervals() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        assert!(tree.peak_intervals().intervals.is_empty());
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        tree.add_segment((2.0, 3.0), 2).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 3);
+        assert_eq!(tree.peak_intervals().intervals, vec![(2.0, 3.0)]);
+    }
+
+    #[test]
+    fn test_concurrent_queries() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SegmentTree>();
+
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 2.5, 3.0, 5.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment((2.5, 5.0), 1).unwrap();
+        let tree = std::sync::Arc::new(tree);
+        let handles: Vec<_> = (0..4).map(|_| {
+            let tree = std::sync::Arc::clone(&tree);
+            std::thread::spawn(move || {
+                let union = tree.get_union((f64::NEG_INFINITY, f64::INFINITY), false, true).unwrap();
+                (union.intervals, tree.root_stats().max_ovp)
+            })
+        }).collect();
+        for handle in handles {
+            let (intervals, max_ovp) = handle.join().unwrap();
+            assert_eq!(intervals, vec![(1.0, 2.0), (2.5, 5.0)]);
+            assert_eq!(max_ovp, 1);
+        }
+    }
+
+    #[test]
+    fn test_from_segments() {
+        let tree = SegmentTree::from_segments(&[((1.0, 3.0), 0), ((2.0, 4.0), 1)]).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 2);
+        assert_eq!(tree.root_stats().length, 3.0);
+        let empty = SegmentTree::from_segments(&[]).unwrap();
+        assert_eq!(empty.nodes.len(), 1);
+        assert!(SegmentTree::from_segments(&[((2.0, 1.0), 0)]).is_err());
+    }
+
+    #[test]
+    fn test_depth_histogram() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(tree.depth_histogram(), vec![2.0]);
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert_eq!(tree.depth_histogram(), vec![0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_segments_in() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 5.0, 6.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment((5.0, 6.0), 1).unwrap();
+        assert_eq!(tree.segments_in((1.0, 3.0)).unwrap(), vec![0]);
+        assert_eq!(tree.segments_in((0.0, 10.0)).unwrap(), vec![0, 1]);
+        assert!(tree.segments_in((2.0, 5.0)).unwrap().is_empty());
+        assert!(tree.segments_in((3.0, f64::NAN)).is_err());
+        // the query is open: a point segment on its bound and point queries find nothing
+        tree.add_segment((2.0, 2.0), 2).unwrap();
+        assert!(tree.segments_in((2.0, 5.0)).unwrap().is_empty());
+        assert_eq!(tree.segments_in((1.0, 5.0)).unwrap(), vec![0, 2]);
+        assert!(tree.segments_in((2.0, 2.0)).unwrap().is_empty());
+        assert!(tree.segments_in((1.5, 1.5)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_memory_bytes() {
+        let ends: Vec<f64> = (0..16).map(|x| x as f64).collect();
+        let mut tree = SegmentTree::new(ends).unwrap();
+        let mut prev = tree.memory_bytes();
+        assert!(prev >= tree.nodes.len() * std::mem::size_of::<Node>());
+        for id in 0..32 {
+            tree.add_segment(((id % 8) as f64, 15.0 - (id % 4) as f64), id).unwrap();
+            let curr = tree.memory_bytes();
+            assert!(curr >= prev);
+            prev = curr;
+        }
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        for id in 0..100 {
+            tree.add_segment((1.0, 4.0), id).unwrap();
+        }
+        for id in 0..100 {
+            tree.remove_segment((1.0, 4.0), id).unwrap();
+        }
+        let before = tree.memory_bytes();
+        tree.shrink_to_fit();
+        assert!(tree.memory_bytes() < before);
+        assert_eq!(tree.root_stats().max_ovp, 0);
+    }
+
+    #[test]
+    fn test_overlaps_any() {
+        let ends: Vec<f64> = (0..32).map(|x| x as f64).collect();
+        let mut tree = SegmentTree::new(ends).unwrap();
+        assert!(!tree.overlaps_any((f64::NEG_INFINITY, f64::INFINITY)).unwrap());
+        tree.add_segment((17.0, 18.0), 0).unwrap();
+        assert!(tree.overlaps_any((17.5, 20.0)).unwrap());
+        assert!(tree.overlaps_any((f64::NEG_INFINITY, f64::INFINITY)).unwrap());
+        assert!(!tree.overlaps_any((18.0, 20.0)).unwrap());
+        assert!(!tree.overlaps_any((0.0, 17.0)).unwrap());
+        assert!(!tree.overlaps_any((17.5, 17.5)).unwrap());
+        assert!(!tree.overlaps_any((18.0, 18.0)).unwrap());
+    }
+
+    #[test]
+    fn test_visit_cover() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        let mut total = 0.0;
+        tree.visit_cover((1.0, 4.0), |itv, segs| {
+            total += (itv.1 - itv.0) * segs.len() as f64;
+            true
+        }).unwrap();
+        assert_eq!(total, 4.0);
+        let mut visited = 0;
+        tree.visit_cover((f64::NEG_INFINITY, f64::INFINITY), |_, _| {
+            visited += 1;
+            false
+        }).unwrap();
+        assert!(visited > 0 && visited < tree.nodes.len());
+    }
+
+    #[test]
+    fn test_single_unique_end() {
+        let mut tree = SegmentTree::new(vec![2.0, 2.0, 2.0]).unwrap();
+        assert_eq!(tree.nodes.len(), 5);
+        assert_eq!(count_leaves(&tree), 3);
+        tree.add_segment((2.0, 2.0), 0).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 1);
+        assert_eq!(tree.root_stats().length, 0.0);
+        let union = tree.get_union((f64::NEG_INFINITY, f64::INFINITY), false, true).unwrap();
+        assert_eq!(union.intervals, vec![(2.0, 2.0)]);
+        assert!(union.contains_point(2.0));
+        assert!(!union.contains_point(2.5));
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let tree = SegmentTree::new(vec![3.0, 1.0, 2.0, 1.0]).unwrap();
+        assert_eq!(tree.endpoints(), vec![1.0, 2.0, 3.0]);
+        assert!(SegmentTree::new(vec![]).unwrap().endpoints().is_empty());
+        for i in 0..32 {
+            let ends: Vec<f64> = (0..i).map(|x| x as f64).collect();
+            assert_eq!(SegmentTree::new(ends.clone()).unwrap().endpoints(), ends);
+        }
+    }
+
+    #[test]
+    fn test_build_structure() {
+        let mut state: u64 = 0x2545F4914F6CDD1D;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % 1000) as f64 / 8.0
+        };
+        for n in 0..64 {
+            let ends: Vec<f64> = (0..n).map(|_| next()).collect();
+            let tree = SegmentTree::new(ends).unwrap();
+            let leaves: Vec<(f64, f64)> = tree.leaves_in_order().map(|i| tree.nodes[i].itv).collect();
+            assert_eq!(leaves.len(), count_leaves(&tree));
+            assert_eq!(leaves.first().unwrap().0, f64::NEG_INFINITY);
+            assert_eq!(leaves.last().unwrap().1, f64::INFINITY);
+            for pair in leaves.windows(2) {
+                assert_eq!(pair[0].1, pair[1].0);
+                assert!(pair[0].0 < pair[1].1);
+            }
+            for i in (0..tree.nodes.len()).filter(|&i| !tree.is_leaf(i)) {
+                assert_eq!(tree.nodes[i].itv, (tree.nodes[i.left()].itv.0, tree.nodes[i.right()].itv.1));
+                assert_eq!(tree.nodes[i.left()].itv.1, tree.nodes[i.right()].itv.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let ends = vec![1.0, 2.0, 3.0, 4.0];
+        let mut tree = SegmentTree::new(ends.clone()).unwrap();
+        let mut other = SegmentTree::new(ends).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        other.add_segment((2.0, 4.0), 1).unwrap();
+        tree.merge(&other).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 2);
+        assert_eq!(tree.root_stats().length, 3.0);
+        assert_eq!(tree.segments_in((2.0, 3.0)).unwrap(), vec![0, 1]);
+        let mismatched = SegmentTree::new(vec![1.0, 2.0]).unwrap();
+        assert!(matches!(tree.merge(&mismatched), Err(InputError::EndpointsMismatch)));
+        let mut colliding = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        colliding.add_segment((3.0, 4.0), 2).unwrap();
+        colliding.add_segment((1.0, 2.0), 0).unwrap();
+        let before = tree.to_state();
+        assert!(matches!(tree.merge(&colliding), Err(InputError::DuplicateId)));
+        assert_eq!(tree.to_state(), before);
+        assert_eq!(tree.segment_interval(0), Some((1.0, 3.0)));
+    }
+
+    #[test]
+    fn test_union_boundary() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        assert_eq!(tree.get_union((2.0, 3.0), true, true).unwrap().intervals, vec![(2.0, 2.0)]);
+        assert!(tree.get_union((2.0, 3.0), false, true).unwrap().intervals.is_empty());
+        assert_eq!(tree.get_union((1.0, 2.0), false, true).unwrap().intervals, vec![(1.0, 2.0)]);
+        assert_eq!(tree.get_union((0.0, 3.0), true, true).unwrap().intervals, vec![(1.0, 2.0)]);
+    }
+
+    #[test]
+    fn test_union_boundary_any_shape() {
+        let layouts = [
+            vec![4.0, 7.0],
+            vec![1.0, 4.0, 7.0],
+            vec![4.0, 7.0, 10.0],
+            vec![1.0, 4.0, 7.0, 10.0],
+            vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0],
+            vec![4.0, 5.0, 7.0, 8.0, 9.0, 10.0, 11.0],
+        ];
+        for ends in layouts {
+            let mut tree = SegmentTree::new(ends.clone()).unwrap();
+            tree.add_segment((4.0, 7.0), 0).unwrap();
+            assert_eq!(tree.get_union((7.0, 9.0), true, true).unwrap().intervals, vec![(7.0, 7.0)], "{ends:?}");
+            // (4, 7] holds its end point, not its start
+            assert!(tree.get_union((2.0, 4.0), true, true).unwrap().intervals.is_empty(), "{ends:?}");
+            assert!(tree.get_union((7.0, 9.0), false, true).unwrap().intervals.is_empty(), "{ends:?}");
+            assert!(tree.get_union((2.0, 4.0), false, true).unwrap().intervals.is_empty(), "{ends:?}");
+            tree.remove_segment((4.0, 7.0), 0).unwrap();
+            assert!(tree.is_empty(), "{ends:?}");
+        }
+    }
+
+    #[test]
+    fn test_depth_union() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 4.0), 1).unwrap();
+        let union = tree.get_union_with_depth((f64::NEG_INFINITY, f64::INFINITY)).unwrap();
+        assert_eq!(union.depth_at(2.5), 2);
+        assert_eq!(union.depth_at(1.5), 1);
+        assert_eq!(union.depth_at(3.5), 1);
+        assert_eq!(union.depth_at(5.0), 0);
+        assert_eq!(union.depth_at(f64::NAN), 0);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        let snap = tree.snapshot();
+        tree.add_segment((2.0, 4.0), 1).unwrap();
+        tree.add_segment((2.0, 3.0), 2).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 3);
+        tree.restore(snap);
+        assert_eq!(tree.root_stats().max_ovp, 1);
+        assert_eq!(tree.root_stats().length, 2.0);
+        assert_eq!(tree.segments_in((0.0, 5.0)).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn test_sort_total_order() {
+        let sub = f64::MIN_POSITIVE / 4.0;
+        let ends = vec![1.0, -0.0, sub, 0.0, -sub, f64::MIN_POSITIVE, -1.0, 0.0, -0.0, sub];
+        let mut reversed = ends.clone();
+        reversed.reverse();
+        let a = SegmentTree::new(ends).unwrap().endpoints();
+        let b = SegmentTree::new(reversed).unwrap().endpoints();
+        assert_eq!(a.iter().map(|x| x.to_bits()).collect::<Vec<_>>(), b.iter().map(|x| x.to_bits()).collect::<Vec<_>>());
+        assert!(a.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(a.len(), 6);
+    }
+
+    #[test]
+    fn test_signed_zero_endpoints() {
+        let a = SegmentTree::new(vec![-0.0, 0.0, 1.0]).unwrap();
+        let b = SegmentTree::new(vec![0.0, 1.0]).unwrap();
+        assert_eq!(a.nodes.len(), b.nodes.len());
+        for (x, y) in a.nodes.iter().zip(b.nodes.iter()) {
+            assert_eq!(x.itv.0.to_bits(), y.itv.0.to_bits());
+            assert_eq!(x.itv.1.to_bits(), y.itv.1.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_add_segment_unbounded() {
+        let mut tree = SegmentTree::new(vec![5.0]).unwrap();
+        assert!(tree.add_segment((5.0, f64::INFINITY), 0).is_err());
+        tree.add_segment_unbounded((5.0, f64::INFINITY), 0).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 1);
+        assert_eq!(tree.root_stats().length, f64::INFINITY);
+        let union = tree.get_union((f64::NEG_INFINITY, f64::INFINITY), false, true).unwrap();
+        assert_eq!(union.intervals, vec![(5.0, f64::INFINITY)]);
+        assert_eq!(tree.segments_in((10.0, 20.0)).unwrap(), vec![0]);
+        tree.remove_segment((5.0, f64::INFINITY), 0).unwrap();
+        assert_eq!(tree.root_stats().max_ovp, 0);
+    }
+
+    #[test]
+    fn test_distinct_overlap_count() {
+        let ends: Vec<f64> = (0..8).map(|x| x as f64).collect();
+        let mut tree = SegmentTree::new(ends).unwrap();
+        tree.add_segment((1.0, 6.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert!(tree.nodes.iter().filter(|node| node.segs.contains(&0)).count() > 1);
+        assert_eq!(tree.distinct_overlap_count((0.0, 10.0)).unwrap(), 2);
+        assert_eq!(tree.distinct_overlap_count((4.0, 5.0)).unwrap(), 1);
+        assert_eq!(tree.distinct_overlap_count((6.0, 7.0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 7).unwrap();
+        let dump = tree.dump();
+        assert_eq!(dump.lines().count(), tree.nodes.len());
+        assert!(dump.starts_with("Node 0: itv=(-inf,inf) segs=[] stats=(length: 1, max_ovp: 1, min_ovp: 0)"));
+        assert!(dump.contains("segs=[7]"));
+        assert_eq!(format!("{:?}", tree), dump);
+    }
+
+    #[test]
+    fn test_first_uncovered() {
+        let mut tree = SegmentTree::new(vec![1.0, 3.0, 5.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        assert_eq!(tree.first_uncovered(2.0).unwrap(), Some(3.0));
+        // an infimum: 3 is the end of (1, 3] and still covered, anything just above is not
+        assert_eq!(tree.depth_at_point(3.0).unwrap(), 1);
+        assert_eq!(tree.depth_at_point(3.5).unwrap(), 0);
+        assert_eq!(tree.first_uncovered(0.0).unwrap(), Some(0.0));
+        assert_eq!(tree.first_uncovered(4.0).unwrap(), Some(4.0));
+        tree.add_segment_unbounded((3.0, f64::INFINITY), 1).unwrap();
+        assert_eq!(tree.first_uncovered(2.0).unwrap(), None);
+        assert!(tree.first_uncovered(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_first_covered() {
+        let mut tree = SegmentTree::new(vec![5.0, 6.0]).unwrap();
+        assert_eq!(tree.first_covered(f64::NEG_INFINITY).unwrap(), None);
+        tree.add_segment((5.0, 6.0), 0).unwrap();
+        assert_eq!(tree.first_covered(0.0).unwrap(), Some(5.0));
+        // an infimum: 5 is the open start of (5, 6]
+        assert_eq!(tree.depth_at_point(5.0).unwrap(), 0);
+        assert_eq!(tree.depth_at_point(5.5).unwrap(), 1);
+        assert_eq!(tree.first_covered(5.5).unwrap(), Some(5.5));
+        assert_eq!(tree.first_covered(7.0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_relabel() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert!(matches!(tree.relabel(0, 1), Err(InputError::DuplicateId)));
+        tree.relabel(0, 5).unwrap();
+        assert_eq!(tree.segments_in((1.0, 2.0)).unwrap(), vec![5]);
+        assert_eq!(tree.segments_in((2.0, 3.0)).unwrap(), vec![1, 5]);
+        assert_eq!(tree.root_stats().max_ovp, 2);
+    }
+
+    #[test]
+    fn test_segs_sorted() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0]).unwrap();
+        for id in [5, 1, 9, 3, 1, 7] {
+            tree.add_segment((1.0, 2.0), id).unwrap();
+        }
+        let node = tree.nodes.iter().find(|node| !node.segs.is_empty()).unwrap();
+        assert_eq!(node.segs, vec![1, 1, 3, 5, 7, 9]);
+        assert_eq!(tree.root_stats().max_ovp, 6);
+        tree.remove_segment((1.0, 2.0), 1).unwrap();
+        tree.relabel(9, 0).unwrap();
+        let node = tree.nodes.iter().find(|node| !node.segs.is_empty()).unwrap();
+        assert_eq!(node.segs, vec![0, 3, 5, 7]);
+        assert_eq!(tree.root_stats().max_ovp, 4);
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment((3.0, 4.0), 1).unwrap();
+        assert_eq!(tree.get_gaps((1.0, 4.0)).unwrap().intervals, vec![(2.0, 3.0)]);
+        assert_eq!(tree.gap_lengths((1.0, 4.0)).unwrap(), vec![1.0]);
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        assert_eq!(tree.get_gaps(all).unwrap().intervals,
+            vec![(f64::NEG_INFINITY, 1.0), (2.0, 3.0), (4.0, f64::INFINITY)]);
+        assert_eq!(tree.gap_lengths(all).unwrap(), vec![1.0]);
+        assert_eq!(tree.gap_lengths((0.0, 5.0)).unwrap(), vec![1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_storage_count() {
+        let ends: Vec<f64> = (0..8).map(|x| x as f64).collect();
+        let mut tree = SegmentTree::new(ends).unwrap();
+        assert_eq!(tree.storage_count(), 0);
+        tree.add_segment((1.0, 6.0), 0).unwrap();
+        assert!(tree.storage_count() > 1);
+        assert_eq!(tree.distinct_overlap_count((f64::NEG_INFINITY, f64::INFINITY)).unwrap(), 1);
+        tree.remove_segment((1.0, 6.0), 0).unwrap();
+        assert_eq!(tree.storage_count(), 0);
+    }
+
+    #[test]
+    fn test_new_sorted() {
+        let tree = SegmentTree::new_sorted(vec![1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(tree.endpoints(), SegmentTree::new(vec![3.0, 1.0, 2.0]).unwrap().endpoints());
+        assert!(SegmentTree::new_sorted(vec![]).is_ok());
+        let unsorted = SegmentTree::new_sorted(vec![2.0, 1.0]).unwrap_err();
+        assert!(matches!(unsorted, InputError::UnsortedEndpoints));
+        assert_eq!(unsorted.to_string(), "Endpoints are not strictly increasing");
+        assert!(matches!(SegmentTree::new_sorted(vec![1.0, 1.0]), Err(InputError::UnsortedEndpoints)));
+        assert!(matches!(SegmentTree::new_sorted(vec![1.0, f64::INFINITY]), Err(InputError::ContainsInfinite)));
+    }
+
+    #[test]
+    fn test_regions_at_least() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert_eq!(tree.regions_at_least(2).intervals, vec![(2.0, 3.0)]);
+        assert_eq!(tree.regions_at_least(1).intervals, vec![(1.0, 4.0)]);
+        assert!(tree.regions_at_least(3).intervals.is_empty());
+        assert_eq!(tree.regions_at_least(0).intervals, vec![(f64::NEG_INFINITY, f64::INFINITY)]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        tree.clear();
+        assert_eq!(tree.root_stats().max_ovp, 0);
+        assert_eq!(tree.root_stats().length, 0.0);
+        assert_eq!(tree.storage_count(), 0);
+        assert_eq!(tree.endpoints(), vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_eq() {
+        let ends = vec![1.0, 2.0, 3.0, 4.0];
+        let mut a = SegmentTree::new(ends.clone()).unwrap();
+        let mut b = SegmentTree::new(ends).unwrap();
+        a.add_segment((1.0, 3.0), 0).unwrap();
+        a.add_segment((2.0, 4.0), 1).unwrap();
+        b.add_segment((2.0, 4.0), 1).unwrap();
+        assert!(a != b);
+        b.add_segment((1.0, 3.0), 0).unwrap();
+        assert!(a == b);
+        b.relabel(1, 2).unwrap();
+        assert!(a != b);
+        assert!(SegmentTree::new(vec![1.0]).unwrap() != SegmentTree::new(vec![2.0]).unwrap());
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 4.0), 1).unwrap();
+        let (ends, storage, originals, lazy, epsilon) = tree.to_state();
+        let restored = SegmentTree::from_state(ends.clone(), storage, originals, lazy, epsilon).unwrap();
+        assert!(restored == tree);
+        assert_eq!(restored.root_stats().max_ovp, 2);
+        assert!(matches!(SegmentTree::from_state(ends, vec![(1000, 0)], vec![], false, 0.0), Err(InputError::InvalidState)));
+    }
+
+    #[test]
+    fn test_union_min_depth() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        assert_eq!(tree.get_union_min_depth(all, 2).unwrap().intervals, vec![(2.0, 3.0)]);
+        assert_eq!(tree.get_union_min_depth(all, 1).unwrap().intervals, tree.get_union(all, false, true).unwrap().intervals);
+        assert_eq!(tree.get_union_min_depth((2.0, 5.0), 1).unwrap().intervals, vec![(2.0, 4.0)]);
+        assert_eq!(tree.get_union_min_depth((2.5, 5.0), 1).unwrap().intervals, vec![(2.5, 4.0)]);
+        assert!(tree.get_union_min_depth(all, 3).unwrap().intervals.is_empty());
+    }
+
+    #[test]
+    fn test_union_clamped_to_query() {
+        let mut tree = SegmentTree::new(vec![1.0, 3.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        assert_eq!(tree.get_union((1.0, 2.5), false, true).unwrap().intervals, vec![(1.0, 2.5)]);
+        assert_eq!(tree.get_union((1.5, 2.5), true, true).unwrap().intervals, vec![(1.5, 2.5)]);
+        assert_eq!(tree.get_union((0.0, 2.0), false, true).unwrap().intervals, vec![(1.0, 2.0)]);
+        assert!(tree.get_union((3.0, 4.0), false, true).unwrap().intervals.is_empty());
+        let depth = tree.get_union_with_depth((2.0, 5.0)).unwrap();
+        assert_eq!(depth.intervals, vec![(2.0, 3.0, 1)]);
+    }
+
+    #[test]
+    fn test_builder() {
+        let ends = vec![3.0, 1.0, 2.0, 1.0, -0.0];
+        let mut builder = SegmentTree::builder();
+        for &end in ends.iter() {
+            builder.push_end(end).unwrap();
+        }
+        assert!(matches!(builder.push_end(f64::NAN), Err(InputError::ContainsNaN)));
+        let tree = builder.finish().unwrap();
+        assert!(tree == SegmentTree::new(ends).unwrap());
+        assert!(SegmentTreeBuilder::with_capacity(4).finish().unwrap() == SegmentTree::new(vec![]).unwrap());
+    }
+
+    #[test]
+    fn test_endpoint_depths() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(tree.endpoint_depths(), vec![(1.0, 0), (2.0, 0), (3.0, 0)]);
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        assert_eq!(tree.endpoint_depths(), vec![(1.0, 0), (2.0, 1), (3.0, 0)]);
+        tree.add_segment((2.0, 2.0), 1).unwrap();
+        tree.add_segment((0.0, 5.0), 2).unwrap();
+        assert_eq!(tree.endpoint_depths(), vec![(1.0, 1), (2.0, 3), (3.0, 1)]);
+
+        // a segment holds its end point and not its start, whatever the tree shape
+        for ends in [vec![1.0, 4.0, 7.0, 10.0], vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]] {
+            let mut tree = SegmentTree::new(ends.clone()).unwrap();
+            tree.add_segment((4.0, 7.0), 0).unwrap();
+            for (end, depth) in tree.endpoint_depths() {
+                assert_eq!(depth, usize::from(4.0 < end && end <= 7.0), "{ends:?} at {end}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_union_uncoalesced() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        tree.add_segment((1.0, 5.0), 0).unwrap();
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        assert_eq!(tree.get_union(all, false, true).unwrap().intervals, vec![(1.0, 5.0)]);
+        let raw = tree.get_union(all, false, false).unwrap().intervals;
+        assert!(raw.len() > 1);
+        assert_eq!(raw.first().unwrap().0, 1.0);
+        assert_eq!(raw.last().unwrap().1, 5.0);
+        assert!(raw.windows(2).all(|w| w[0].1 == w[1].0));
+    }
+
+    #[test]
+    fn test_union_set_ops() {
+        let a = Union { intervals: vec![(1.0, 3.0), (5.0, 7.0)] };
+        let b = Union { intervals: vec![(2.0, 6.0), (7.0, 8.0)] };
+        assert_eq!(a.intersection(&b).intervals, vec![(2.0, 3.0), (5.0, 6.0)]);
+        assert_eq!(a.union(&b).intervals, vec![(1.0, 8.0)]);
+        assert_eq!(a.length(), 4.0);
+    }
+
+    #[test]
+    fn test_jaccard() {
+        let ends = vec![1.0, 2.0, 3.0, 4.0];
+        let mut a = SegmentTree::new(ends.clone()).unwrap();
+        let mut b = SegmentTree::new(ends).unwrap();
+        assert_eq!(a.jaccard(&b).unwrap(), 1.0);
+        a.add_segment((1.0, 2.0), 0).unwrap();
+        b.add_segment((3.0, 4.0), 0).unwrap();
+        assert_eq!(a.jaccard(&b).unwrap(), 0.0);
+        b.remove_segment((3.0, 4.0), 0).unwrap();
+        b.add_segment((1.0, 2.0), 0).unwrap();
+        assert_eq!(a.jaccard(&b).unwrap(), 1.0);
+        b.add_segment((2.0, 4.0), 1).unwrap();
+        assert!((a.jaccard(&b).unwrap() - 1.0 / 3.0).abs() < 1e-12);
+        b.add_segment_unbounded((4.0, f64::INFINITY), 2).unwrap();
+        assert!(matches!(a.jaccard(&b), Err(InputError::ContainsInfinite)));
+        assert!(matches!(b.jaccard(&a), Err(InputError::ContainsInfinite)));
     }
 
     #[test]
-    fn test_empty() {
-        let tree = SegmentTree::new(vec![]).unwrap();
-        assert_eq!(tree.root_stats().max_ovp, 0);
-        assert_eq!(tree.root_stats().length, 0.0);
-        assert_eq!(tree.nodes.len(), 1);
+    fn test_global_overlap() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        tree.add_segment_unbounded((f64::NEG_INFINITY, f64::INFINITY), 2).unwrap();
+        assert_eq!(tree.global_min_overlap(), tree.root_stats().min_ovp);
+        assert_eq!(tree.global_max_overlap(), tree.root_stats().max_ovp);
+        assert_eq!((tree.global_min_overlap(), tree.global_max_overlap()), (1, 3));
     }
 
     #[test]
-    fn test_no_segments() {
-        let tree = SegmentTree::new(vec![1.0, 2.0]).unwrap();
+    fn test_add_segment_infinite_error() {
+        let mut tree = SegmentTree::new(vec![5.0]).unwrap();
+        let err = tree.add_segment((5.0, f64::INFINITY), 0).unwrap_err();
+        assert_eq!(err.to_string(), "Segment end is infinite, use add_segment_unbounded for open-ended segments");
+        let err = tree.add_segment((f64::NEG_INFINITY, 5.0), 0).unwrap_err();
+        assert!(err.to_string().starts_with("Segment start is infinite"));
+        assert!(matches!(tree.add_segment((f64::NAN, 5.0), 0), Err(InputError::ContainsNaN)));
         assert_eq!(tree.root_stats().max_ovp, 0);
-        assert_eq!(tree.root_stats().length, 0.0);
     }
 
     #[test]
-    fn test_n_nodes(){
-        for i in 0..32 {
-            let ends: Vec<f64> = (0..i).map(|x| x as f64).collect();
-            let tree = SegmentTree::new(ends).unwrap();
-            assert_eq!(tree.nodes.len(), (i << 2) + 1);
+    fn test_new_with_epsilon() {
+        let merged = SegmentTree::new_with_epsilon(vec![1.0, 1.0 + 1e-12, 2.0], 1e-9).unwrap();
+        assert_eq!(merged.endpoints(), vec![1.0, 2.0]);
+        let exact = SegmentTree::new_with_epsilon(vec![1.0, 1.0 + 1e-12, 2.0], 0.0).unwrap();
+        assert_eq!(exact.endpoints().len(), 3);
+        // runs are measured from their first endpoint, so they do not drift
+        let chained = SegmentTree::new_with_epsilon(vec![0.0, 0.6, 1.2, 1.8], 1.0).unwrap();
+        assert_eq!(chained.endpoints(), vec![0.0, 1.2]);
+        assert!(matches!(SegmentTree::new_with_epsilon(vec![1.0], -1.0), Err(InputError::NegativeEpsilon)));
+    }
+
+    #[test]
+    fn test_to_arrays() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        let (starts, ends, counts) = tree.to_arrays();
+        assert_eq!(starts.len(), tree.node_count());
+        assert_eq!(ends.len(), tree.node_count());
+        assert_eq!(counts.len(), tree.node_count());
+        assert_eq!((starts[0], ends[0]), (f64::NEG_INFINITY, f64::INFINITY));
+        assert_eq!(counts.iter().sum::<usize>(), tree.storage_count());
+    }
+
+    #[test]
+    fn test_longest_covered() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 4.0, 7.0, 8.0, 11.0]).unwrap();
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        assert_eq!(tree.longest_covered(all).unwrap(), None);
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment((4.0, 7.0), 1).unwrap();
+        assert_eq!(tree.longest_covered(all).unwrap(), Some((4.0, 7.0)));
+        tree.add_segment((8.0, 11.0), 2).unwrap();
+        assert_eq!(tree.longest_covered(all).unwrap(), Some((4.0, 7.0)));
+        assert_eq!(tree.longest_covered((0.0, 5.0)).unwrap(), Some((1.0, 2.0)));
+    }
+
+    #[test]
+    fn test_coverage_split() {
+        let mut tree = SegmentTree::new(vec![1.0, 5.0, 6.0, 7.0]).unwrap();
+        tree.add_segment((1.0, 5.0), 0).unwrap();
+        tree.add_segment((6.0, 7.0), 1).unwrap();
+        let (left, right) = tree.coverage_split(3.0).unwrap();
+        assert_eq!(left.intervals, vec![(1.0, 3.0)]);
+        assert_eq!(right.intervals, vec![(3.0, 5.0), (6.0, 7.0)]);
+        let (left, right) = tree.coverage_split(5.5).unwrap();
+        assert_eq!(left.intervals, vec![(1.0, 5.0)]);
+        assert_eq!(right.intervals, vec![(6.0, 7.0)]);
+        assert!(tree.coverage_split(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_visit_union() {
+        let mut tree = SegmentTree::new(vec![0.0, 1.0, 2.0, 3.0, 5.0, 6.0]).unwrap();
+        tree.add_segment((0.0, 1.0), 0).unwrap();
+        tree.add_segment((1.0, 3.0), 1).unwrap();
+        tree.add_segment((5.0, 6.0), 2).unwrap();
+        let mut seen = Vec::new();
+        tree.visit_union((-1.0, 10.0), |itv| { seen.push(itv); true }).unwrap();
+        assert_eq!(seen, tree.get_union((-1.0, 10.0), false, true).unwrap().intervals);
+        let mut first = Vec::new();
+        tree.visit_union((-1.0, 10.0), |itv| { first.push(itv); false }).unwrap();
+        assert_eq!(first, vec![(0.0, 3.0)]);
+    }
+
+    #[test]
+    fn test_ids_overlap() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 4.0), 1).unwrap();
+        tree.add_segment((4.0, 6.0), 2).unwrap();
+        assert!(tree.ids_overlap(0, 1));
+        assert!(tree.ids_overlap(1, 0));
+        assert!(!tree.ids_overlap(0, 2));
+        assert!(!tree.ids_overlap(1, 2));
+        assert!(!tree.ids_overlap(0, 7));
+
+        for ends in [vec![1.0, 2.0, 7.0], vec![2.0, 7.0], vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]] {
+            let mut tree = SegmentTree::new(ends.clone()).unwrap();
+            tree.add_segment((2.0, 7.0), 0).unwrap();
+            tree.add_segment((2.0, 2.0), 1).unwrap();
+            tree.add_segment((7.0, 7.0), 2).unwrap();
+            tree.add_segment((7.0, 8.0), 3).unwrap();
+            tree.add_segment((1.0, 2.0), 4).unwrap();
+            // (2, 7] holds the point 7 but not 2, which (1, 2] does
+            assert!(tree.ids_overlap(2, 0), "{ends:?}");
+            assert!(tree.ids_overlap(1, 4), "{ends:?}");
+            assert!(!tree.ids_overlap(0, 1), "{ends:?}");
+            assert!(!tree.ids_overlap(0, 3), "{ends:?}");
+            assert!(!tree.ids_overlap(0, 4), "{ends:?}");
+            assert!(!tree.ids_overlap(2, 3), "{ends:?}");
         }
     }
 
     #[test]
-    fn test_n_leaves(){
-        for i in 0..32 {
-            let ends: Vec<f64> = (0..i).map(|x| x as f64).collect();
-            println!("ends: {:?}", ends);
-            let tree = SegmentTree::new(ends).unwrap();
-            print_tree(&tree);
-            let n_leaves = count_leaves(&tree);
-            println!("{}: {}", i, n_leaves);
-            assert_eq!(n_leaves, (i<<1)+1);
+    fn test_reserve_segments() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        let before = tree.memory_bytes();
+        tree.reserve_segments(8);
+        assert!(tree.nodes.iter().all(|node| node.segs.capacity() >= 8));
+        assert!(tree.memory_bytes() > before);
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        assert!(tree.get_union((0.0, 4.0), false, true).unwrap().contains_point(2.0));
+    }
+
+    #[test]
+    fn test_depth_transitions() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 6.0, 7.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 4.0), 1).unwrap();
+        assert_eq!(tree.depth_transitions((0.0, 5.0)).unwrap(), vec![(1.0, 1), (2.0, 1), (3.0, -1), (4.0, -1)]);
+        tree.add_segment((6.0, 7.0), 2).unwrap();
+        assert_eq!(tree.depth_transitions((3.5, 6.5)).unwrap(), vec![(3.5, 1), (4.0, -1), (6.0, 1), (6.5, -1)]);
+    }
+
+    #[test]
+    fn test_add_segment_leaf_boundaries() {
+        // 9 leaves: (-inf,1) and [1,1] on the incomplete last level, the rest one level up
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment_unbounded((4.0, f64::INFINITY), 1).unwrap();
+        tree.add_segment_unbounded((f64::NEG_INFINITY, 1.0), 2).unwrap();
+        let before = tree.snapshot();
+        assert!(tree.add_segment((1.0, f64::NAN), 3).is_err());
+        assert!(tree.add_segment((3.0, 2.0), 3).is_err());
+        assert!(tree.add_segment((3.0, f64::INFINITY), 3).is_err());
+        let mut restored = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        restored.restore(before);
+        assert_eq!(tree, restored);
+        assert_eq!(tree.get_union((0.0, 5.0), false, true).unwrap().intervals, vec![(0.0, 2.0), (4.0, 5.0)]);
+        assert_eq!(tree.segments_in((1.5, 4.5)).unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_leaf_interval_segments_stored_nodes() {
+        // 4n + 1 nodes is never a full tree, so every size has an incomplete last level; each leaf
+        // interval, on either level, goes in as a segment and must land on exactly its leaves
+        for n_ends in 1..=12 {
+            let mut tree = SegmentTree::new((1..=n_ends).map(f64::from).collect()).unwrap();
+            let compl_tree_size = (1usize << (tree.nodes.len() + 1).ilog2()) - 1;
+            let last_complete: Vec<usize> = ((tree.nodes.len() >> 1)..compl_tree_size).collect();
+            assert!(!last_complete.is_empty() && compl_tree_size < tree.nodes.len());
+            let leaves: Vec<usize> = tree.leaves_in_order().collect();
+            for &leaf in &leaves {
+                let seg = tree.nodes[leaf].itv;
+                let holds = |itv: (f64, f64)| if itv.0 == itv.1 {
+                    (seg.0 < itv.0 && itv.0 <= seg.1) || seg == itv
+                } else {
+                    seg.0 <= itv.0 && itv.1 <= seg.1
+                };
+                tree.add_segment_unbounded(seg, 0).unwrap();
+                let stored: Vec<usize> = (0..tree.nodes.len()).filter(|&i| tree.nodes[i].has_seg(0)).collect();
+                // a leaf is covered once per stored node above it
+                let covering = |leaf: usize| stored.iter().filter(|&&node| {
+                    let mut i = leaf;
+                    while i != node && i != 0 {
+                        i = (i - 1) >> 1;
+                    }
+                    i == node
+                }).count();
+                for &other in &leaves {
+                    let expected = usize::from(holds(tree.nodes[other].itv));
+                    assert_eq!(covering(other), expected, "n_ends {n_ends}, segment {seg:?}, leaf {other}");
+                }
+                // canonical: a stored node's sibling is never stored too
+                assert!(stored.iter().all(|&i| i == 0 || !stored.contains(&if i % 2 == 1 { i + 1 } else { i - 1 })));
+                if seg.0 == seg.1 {
+                    assert_eq!(stored, vec![leaf]);
+                    assert_eq!(tree.depth_at_point(seg.0).unwrap(), 1);
+                } else if seg.0.is_finite() && seg.1.is_finite() {
+                    assert_eq!(tree.depth_at_point((seg.0 + seg.1) / 2.0).unwrap(), 1);
+                    assert_eq!(tree.depth_at_point(seg.1).unwrap(), 1);
+                    assert_eq!(tree.depth_at_point(seg.0).unwrap(), 0);
+                }
+                assert_eq!(tree.check_invariants(), Ok(()));
+                tree.remove_segment(seg, 0).unwrap();
+                assert!(tree.is_empty() && tree.root_stats().max_ovp == 0);
+            }
         }
     }
 
     #[test]
-    fn test_add_segment() {
-        let mut tree = SegmentTree::new(vec![1.0, 2.0]).unwrap();
-        print_tree(&tree);
+    fn test_covered_length_before() {
+        let mut tree = SegmentTree::new(vec![1.0, 5.0, 6.0, 8.0]).unwrap();
+        tree.add_segment((1.0, 5.0), 0).unwrap();
+        tree.add_segment((6.0, 8.0), 1).unwrap();
+        assert_eq!(tree.covered_length_before(3.0).unwrap(), 2.0);
+        assert_eq!(tree.covered_length_before(0.0).unwrap(), 0.0);
+        assert_eq!(tree.covered_length_before(7.0).unwrap(), 5.0);
+        assert!(tree.covered_length_before(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_all_stats() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        let all = tree.all_stats();
+        assert_eq!(all.len(), tree.node_count());
+        assert_eq!(all[0].0, (f64::NEG_INFINITY, f64::INFINITY));
+        assert!(all[0].1 == tree.root_stats());
+    }
+
+    #[test]
+    fn test_contains_interval() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
         tree.add_segment((1.0, 2.0), 0).unwrap();
-        print_tree(&tree);
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        tree.add_segment((4.0, 5.0), 2).unwrap();
+        let union = tree.get_union((0.0, 6.0), false, true).unwrap();
+        assert_eq!(union.intervals, vec![(1.0, 3.0), (4.0, 5.0)]);
+        assert!(union.contains_interval((1.5, 2.5)));
+        assert!(union.contains_interval((1.0, 3.0)));
+        assert!(!union.contains_interval((2.5, 4.5)));
+        assert!(!union.contains_interval((5.0, 6.0)));
+        assert!(!union.contains_interval((1.0, f64::NAN)));
+    }
+
+    #[test]
+    fn test_component_count() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 4.0, 5.0, 7.0, 8.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment((4.0, 5.0), 1).unwrap();
+        tree.add_segment((7.0, 8.0), 2).unwrap();
+        assert_eq!(tree.component_count((0.0, 10.0)).unwrap(), 3);
+        assert_eq!(tree.component_count((1.5, 4.5)).unwrap(), 2);
+        assert_eq!(tree.component_count((2.0, 4.0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_try_contains_point() {
+        let union = Union { intervals: vec![(1.0, 2.0)] };
+        assert!(matches!(union.try_contains_point(f64::NAN), Err(InputError::ContainsNaN)));
+        assert!(union.try_contains_point(1.5).unwrap());
+        assert!(!union.try_contains_point(f64::INFINITY).unwrap());
+        assert!(!union.contains_point(f64::NAN));
+    }
+
+    #[test]
+    fn test_next_interval() {
+        let union = Union { intervals: vec![(1.0, 2.0), (5.0, 6.0)] };
+        assert_eq!(union.next_interval(3.0).unwrap(), Some((5.0, 6.0)));
+        assert_eq!(union.next_interval(1.5).unwrap(), Some((1.0, 2.0)));
+        assert_eq!(union.next_interval(f64::NEG_INFINITY).unwrap(), Some((1.0, 2.0)));
+        assert_eq!(union.next_interval(6.5).unwrap(), None);
+        assert!(union.next_interval(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_new_lazy() {
+        let mut tree = SegmentTree::new_lazy();
+        assert_eq!(tree.node_count(), 1);
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        assert_eq!(tree.endpoints(), vec![1.0, 3.0]);
         assert_eq!(tree.root_stats().max_ovp, 1);
-        assert_eq!(tree.root_stats().length, 1.0);
+        tree.add_segment_unbounded((2.0, f64::INFINITY), 1).unwrap();
+        assert_eq!(tree.endpoints(), vec![1.0, 2.0, 3.0]);
+        assert_eq!(tree.root_stats().max_ovp, 2);
+        assert_eq!(tree.segments_in((1.5, 1.6)).unwrap(), vec![0]);
+        assert_eq!(tree.get_union((0.0, 10.0), false, true).unwrap().intervals, vec![(1.0, 10.0)]);
+        tree.add_segment((1.0, 2.0), 2).unwrap();
+        assert_eq!(tree.endpoints(), vec![1.0, 2.0, 3.0]);
+        assert_eq!(tree.endpoint_depths(), vec![(1.0, 0), (2.0, 2), (3.0, 2)]);
     }
-    
+
     #[test]
-    fn test_remove_segment() {
+    fn test_lazy_state_round_trip() {
+        let mut tree = SegmentTree::new_lazy();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        let (ends, storage, originals, lazy, epsilon) = tree.to_state();
+        assert!(lazy);
+        let mut restored = SegmentTree::from_state(ends, storage, originals, lazy, epsilon).unwrap();
+        assert_eq!(restored, tree);
+        // still inserts missing endpoints, so the new segment covers what it should
+        restored.add_segment_deferred((4.0, 6.0), 1).unwrap();
+        restored.recompute_stats();
+        assert_eq!(restored.endpoints(), vec![1.0, 3.0, 4.0, 6.0]);
+        assert_eq!(restored.covered_length(), 4.0);
+        let (ends, storage, originals, lazy, epsilon) = SegmentTree::new(vec![1.0]).unwrap().to_state();
+        assert!(!SegmentTree::from_state(ends, storage, originals, lazy, epsilon).unwrap().lazy);
+    }
+
+    #[test]
+    fn test_covered_length() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        assert_eq!(tree.covered_length(), 0.0);
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment((3.0, 5.0), 1).unwrap();
+        tree.add_segment((3.0, 4.0), 2).unwrap();
+        assert_eq!(tree.covered_length(), 3.0);
+        assert_eq!(tree.covered_length(), tree.root_stats().length);
+        let everything = tree.get_union((f64::NEG_INFINITY, f64::INFINITY), false, true).unwrap();
+        assert_eq!(tree.covered_length(), everything.length());
+    }
+
+    #[test]
+    fn test_union_iter() {
+        let mut tree = SegmentTree::new((0..20).map(|x| x as f64).collect()).unwrap();
+        for (id, &(start, end)) in [(1.0, 4.0), (3.0, 6.0), (8.0, 9.0), (9.0, 11.0), (15.0, 15.0), (17.0, 30.0)].iter().enumerate() {
+            tree.add_segment((start, end), id).unwrap();
+        }
+        for query in [(-1.0, 25.0), (3.5, 8.5), (9.0, 15.0), (12.0, 14.0)] {
+            let expected = tree.get_union(query, false, true).unwrap().intervals;
+            assert_eq!(tree.union_iter(query).unwrap().collect::<Vec<_>>(), expected);
+        }
+        assert_eq!(tree.union_iter((0.0, 25.0)).unwrap().nth(1), Some((8.0, 11.0)));
+        assert!(tree.union_iter((f64::NAN, 1.0)).is_err());
+    }
+
+    #[test]
+    fn test_depth_at_point() {
+        let mut tree = SegmentTree::new(vec![1.0, 4.0, 7.0, 10.0, 12.0]).unwrap();
+        tree.add_segment((4.0, 7.0), 0).unwrap();
+        tree.add_segment((1.0, 4.0), 1).unwrap();
+        tree.add_segment((7.0, 7.0), 2).unwrap();
+        tree.add_segment((4.0, 12.0), 3).unwrap();
+        let expected = [
+            (0.5, 0), (1.0, 0), (2.5, 1), (4.0, 1), (5.5, 2),
+            (7.0, 3), (8.0, 1), (10.0, 1), (12.0, 1), (13.0, 0),
+        ];
+        for (point, depth) in expected {
+            assert_eq!(tree.depth_at_point(point).unwrap(), depth, "at {point}");
+        }
+        let mut single = SegmentTree::new(vec![1.0, 4.0, 7.0, 10.0]).unwrap();
+        single.add_segment((4.0, 7.0), 0).unwrap();
+        assert_eq!(single.depth_at_point(4.0).unwrap(), 0);
+        assert_eq!(single.depth_at_point(7.0).unwrap(), 1);
+        assert_eq!(single.depth_at_point(7.5).unwrap(), 0);
+        assert!(tree.depth_at_point(f64::NAN).is_err());
+        assert!(tree.depth_at_point(f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn test_coverage_bounds() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 7.0, 9.0, 10.0]).unwrap();
+        assert_eq!(tree.coverage_bounds(), None);
+        tree.add_segment((2.0, 3.0), 0).unwrap();
+        tree.add_segment((7.0, 9.0), 1).unwrap();
+        assert_eq!(tree.coverage_bounds(), Some((2.0, 9.0)));
+        tree.add_segment_unbounded((f64::NEG_INFINITY, 1.0), 2).unwrap();
+        assert_eq!(tree.coverage_bounds(), Some((f64::NEG_INFINITY, 9.0)));
+    }
+
+    #[test]
+    fn test_add_segment_deferred() {
+        let ends: Vec<f64> = (0..200).map(|x| x as f64).collect();
+        let mut eager = SegmentTree::new(ends.clone()).unwrap();
+        let mut deferred = SegmentTree::new(ends).unwrap();
+        for id in 0..1000 {
+            let start = ((id * 37) % 190) as f64;
+            let seg = (start, start + ((id * 11) % 10) as f64);
+            eager.add_segment(seg, id).unwrap();
+            deferred.add_segment_deferred(seg, id).unwrap();
+        }
+        assert_eq!(deferred.storage_count(), eager.storage_count());
+        assert!(deferred != eager);
+        deferred.recompute_stats();
+        assert_eq!(deferred, eager);
+        assert!(matches!(deferred.add_segment_deferred((1.0, f64::INFINITY), 0), Err(InputError::InfiniteSegmentEnd("end"))));
+        assert!(matches!(deferred.add_segment_deferred((f64::NEG_INFINITY, 1.0), 0), Err(InputError::InfiniteSegmentEnd("start"))));
+        assert!(matches!(deferred.add_segment_deferred((1.0, f64::NAN), 0), Err(InputError::ContainsNaN)));
+    }
+
+    #[test]
+    fn test_point_segment_depth() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        tree.add_segment((2.0, 2.0), 0).unwrap();
+        assert_eq!(tree.storage_count(), 1);
+        assert_eq!(tree.depth_at_point(2.0).unwrap(), 1);
+        for query in [0.0, 1.0, 1.5, 1.999, 2.001, 2.5, 3.0, 4.0] {
+            assert_eq!(tree.depth_at_point(query).unwrap(), 0);
+        }
+        assert_eq!(tree.root_stats().max_ovp, 1);
+        assert_eq!(tree.covered_length(), 0.0);
+    }
+
+    #[test]
+    fn test_symmetric_difference() {
+        let a = SegmentTree::from_segments(&[((1.0, 5.0), 0)]).unwrap();
+        let b = SegmentTree::from_segments(&[((3.0, 7.0), 0)]).unwrap();
+        assert_eq!(a.symmetric_difference(&b).unwrap().intervals, vec![(1.0, 3.0), (5.0, 7.0)]);
+        assert_eq!(b.symmetric_difference(&a).unwrap().intervals, vec![(1.0, 3.0), (5.0, 7.0)]);
+        assert!(a.symmetric_difference(&a).unwrap().intervals.is_empty());
+        let holes = Union { intervals: vec![(0.0, 1.0), (2.0, 3.0), (4.0, 9.0)] };
+        let whole = Union { intervals: vec![(0.5, 8.0)] };
+        assert_eq!(whole.difference(&holes).intervals, vec![(1.0, 2.0), (3.0, 4.0)]);
+        assert_eq!(holes.difference(&whole).intervals, vec![(0.0, 0.5), (8.0, 9.0)]);
+    }
+
+    #[test]
+    fn test_endpoint_utilization() {
+        let mut tree = SegmentTree::new((1..=8).map(|x| x as f64).collect()).unwrap();
+        assert_eq!(tree.endpoint_utilization(), 0.0);
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        assert_eq!(tree.endpoint_utilization(), 0.5);
+        tree.add_segment((7.0, 7.0), 1).unwrap();
+        assert_eq!(tree.endpoint_utilization(), 0.625);
+        assert_eq!(SegmentTree::new(vec![]).unwrap().endpoint_utilization(), 0.0);
+    }
+
+    #[test]
+    fn test_depth_range() {
+        let mut tree = SegmentTree::new((0..10).map(|x| x as f64).collect()).unwrap();
+        tree.add_segment((1.0, 6.0), 0).unwrap();
+        tree.add_segment((4.0, 8.0), 1).unwrap();
+        assert_eq!(tree.depth_range((2.0, 5.0)).unwrap(), (1, 2));
+        assert_eq!(tree.depth_range((1.5, 3.5)).unwrap(), (1, 1));
+        assert_eq!(tree.depth_range((4.0, 6.0)).unwrap(), (2, 2));
+        assert_eq!(tree.depth_range((0.0, 9.0)).unwrap(), (0, 2));
+        assert_eq!(tree.depth_range((f64::NEG_INFINITY, f64::INFINITY)).unwrap(), (0, 2));
+        assert_eq!(tree.depth_range((5.0, 5.0)).unwrap(), (2, 2));
+    }
+
+    #[test]
+    fn test_union_sparse_pruning() {
+        let mut tree = SegmentTree::new((0..1000).map(|x| x as f64).collect()).unwrap();
+        assert!(tree.get_union((f64::NEG_INFINITY, f64::INFINITY), false, true).unwrap().intervals.is_empty());
+        tree.add_segment((10.0, 12.0), 0).unwrap();
+        tree.add_segment((500.0, 500.0), 1).unwrap();
+        tree.add_segment((900.0, 990.0), 2).unwrap();
+        let expected = vec![(10.0, 12.0), (500.0, 500.0), (900.0, 990.0)];
+        assert_eq!(tree.get_union((f64::NEG_INFINITY, f64::INFINITY), false, true).unwrap().intervals, expected);
+        assert_eq!(tree.get_union((11.0, 950.0), false, true).unwrap().intervals, vec![(11.0, 12.0), (500.0, 500.0), (900.0, 950.0)]);
+        assert!(tree.get_union((20.0, 400.0), false, true).unwrap().intervals.is_empty());
+    }
+
+    #[test]
+    fn test_union_sparse_visits() {
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        let visits = |tree: &SegmentTree, query: (f64, f64)| {
+            UNION_VISITS.with(|visits| visits.set(0));
+            tree.get_union(query, false, true).unwrap();
+            UNION_VISITS.with(|visits| visits.get())
+        };
+        let ends: Vec<f64> = (0..1000).map(|x| x as f64).collect();
+        let mut tree = SegmentTree::new(ends.clone()).unwrap();
+        assert_eq!(visits(&tree, all), 1);
+        // one short segment: the root-to-storage paths, a few nodes per level
+        tree.add_segment((10.0, 12.0), 0).unwrap();
+        let height = tree.height();
+        let sparse = visits(&tree, all);
+        assert!(sparse <= 4 * (height + 1), "{sparse} visits at height {height}");
+        assert!(visits(&tree, (20.0, 400.0)) <= 4 * (height + 1));
+        // every other unit covered: no subtree can be pruned
+        let mut dense = SegmentTree::new(ends).unwrap();
+        for i in 0..500 {
+            dense.add_segment(((2 * i) as f64, (2 * i + 1) as f64), i).unwrap();
+        }
+        assert!(visits(&dense, all) > 50 * sparse);
+    }
+
+    #[test]
+    fn test_new_with_options() {
+        let deduped = SegmentTree::new_with_options(vec![1.0, 1.0, 2.0], true).unwrap();
+        let kept = SegmentTree::new_with_options(vec![2.0, 1.0, 1.0], false).unwrap();
+        assert_eq!(deduped.node_count(), 9);
+        assert_eq!(kept.node_count(), 13);
+        assert_eq!(deduped.endpoints(), vec![1.0, 2.0]);
+        assert_eq!(kept.endpoints(), vec![1.0, 1.0, 2.0]);
+        let (ends, storage, originals, lazy, epsilon) = kept.to_state();
+        assert_eq!(SegmentTree::from_state(ends, storage, originals, lazy, epsilon).unwrap(), kept);
+        assert!(SegmentTree::new_with_options(vec![1.0, f64::NAN], false).is_err());
+    }
+
+    #[test]
+    fn test_contained_in() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        tree.add_segment((2.0, 3.0), 0).unwrap();
+        tree.add_segment((1.0, 5.0), 1).unwrap();
+        tree.add_segment((3.0, 4.0), 2).unwrap();
+        assert_eq!(tree.contained_in((1.5, 4.5)).unwrap(), vec![0, 2]);
+        assert_eq!(tree.segments_in((1.5, 4.5)).unwrap(), vec![0, 1, 2]);
+        assert_eq!(tree.contained_in((1.0, 5.0)).unwrap(), vec![0, 1, 2]);
+        tree.remove_segment((3.0, 4.0), 2).unwrap();
+        tree.relabel(0, 7).unwrap();
+        assert_eq!(tree.contained_in((1.5, 4.5)).unwrap(), vec![7]);
+        tree.clear();
+        assert!(tree.contained_in((0.0, 6.0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_segment_interval() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.5, 3.25), 0).unwrap();
+        tree.add_segment_unbounded((2.0, f64::INFINITY), 1).unwrap();
+        assert_eq!(tree.segment_interval(0), Some((1.5, 3.25)));
+        assert_eq!(tree.segment_interval(1), Some((2.0, f64::INFINITY)));
+        assert_eq!(tree.segment_interval(2), None);
+        tree.remove_segment((1.5, 3.25), 0).unwrap();
+        assert_eq!(tree.segment_interval(0), None);
+        let (ends, storage, originals, lazy, epsilon) = tree.to_state();
+        assert_eq!(SegmentTree::from_state(ends, storage, originals, lazy, epsilon).unwrap().segment_interval(1), Some((2.0, f64::INFINITY)));
+        tree.clear();
+        assert_eq!(tree.segment_interval(1), None);
+    }
+
+    #[test]
+    fn test_remove_by_id() {
+        let mut tree = SegmentTree::new(vec![1.0, 3.0, 5.0]).unwrap();
+        tree.add_segment((1.0, 5.0), 3).unwrap();
+        tree.add_segment((1.0, 3.0), 4).unwrap();
+        assert!(tree.remove(3).unwrap());
+        assert!(!tree.remove(3).unwrap());
+        assert_eq!(tree.get_union((1.0, 5.0), false, true).unwrap().intervals, vec![(1.0, 3.0)]);
+        assert_eq!(tree.root_stats().max_ovp, 1);
+    }
+
+    #[test]
+    fn test_coverage_integral() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert_eq!(tree.coverage_integral((0.0, 4.0)).unwrap(), 3.0);
+        assert_eq!(tree.coverage_integral((1.5, 2.5)).unwrap(), 1.5);
+        assert_eq!(tree.coverage_integral((3.0, 4.0)).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert_eq!(tree.breakpoints((0.0, 5.0)).unwrap(), vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(tree.breakpoints((2.5, 5.0)).unwrap(), vec![2.5, 3.0, 4.0]);
+        tree.add_segment((5.0, 5.0), 2).unwrap();
+        assert_eq!(tree.breakpoints((4.5, 6.0)).unwrap(), vec![5.0]);
+    }
+
+    #[test]
+    fn test_tree_size_overflow() {
+        assert_eq!(SegmentTree::tree_size(0).unwrap(), 1);
+        assert_eq!(SegmentTree::tree_size(3).unwrap(), 13);
+        for n_ends in [usize::MAX, usize::MAX / 2, usize::MAX / 4, usize::MAX / 64] {
+            assert!(matches!(SegmentTree::tree_size(n_ends), Err(InputError::TooLarge)));
+        }
+    }
+
+    #[test]
+    fn test_is_line_covered() {
         let mut tree = SegmentTree::new(vec![1.0, 2.0]).unwrap();
-        print_tree(&tree);
+        assert!(!tree.is_line_covered());
         tree.add_segment((1.0, 2.0), 0).unwrap();
-        print_tree(&tree);
-        tree.remove_segment((1.0, 2.0), 0).unwrap();
-        print_tree(&tree);
-        assert_eq!(tree.root_stats().max_ovp, 0);
-        assert_eq!(tree.root_stats().length, 0.0);
+        assert!(!tree.is_line_covered());
+        tree.add_segment_unbounded((f64::NEG_INFINITY, 1.0), 1).unwrap();
+        assert!(!tree.is_line_covered());
+        tree.add_segment_unbounded((f64::NEG_INFINITY, f64::INFINITY), 3).unwrap();
+        assert!(tree.is_line_covered());
+        tree.remove_segment((f64::NEG_INFINITY, f64::INFINITY), 3).unwrap();
+        assert!(!tree.is_line_covered());
     }
 
     #[test]
-    fn test_overlap() {
-        let mut tree = SegmentTree::new(vec![1.0, 2.0, 2.5, 3.0]).unwrap();
-        tree.add_segment((1.0, 2.5), 0).unwrap();
+    fn test_subtree() {
+        let mut tree = SegmentTree::new((0..10).map(|x| x as f64).collect()).unwrap();
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((3.0, 8.0), 1).unwrap();
+        tree.add_segment((8.5, 9.0), 2).unwrap();
+        let sub = tree.subtree((2.5, 6.0)).unwrap();
+        assert_eq!(sub.endpoints(), vec![2.5, 3.0, 4.0, 5.0, 6.0]);
+        let (lo, hi) = sub.coverage_bounds().unwrap();
+        assert!(2.5 <= lo && hi <= 6.0);
+        assert_eq!(sub.segment_interval(0), Some((2.5, 4.0)));
+        assert_eq!(sub.segment_interval(1), Some((3.0, 6.0)));
+        assert_eq!(sub.segment_interval(2), None);
+        assert_eq!(sub.get_union((0.0, 10.0), false, true).unwrap().intervals, vec![(2.5, 6.0)]);
+        assert!(tree.subtree((f64::NEG_INFINITY, f64::INFINITY)).unwrap().coverage_bounds() == tree.coverage_bounds());
+    }
+
+    #[test]
+    fn test_upsert() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        tree.upsert((1.0, 3.0), 0).unwrap();
+        tree.upsert((3.0, 5.0), 0).unwrap();
+        tree.upsert((3.0, 5.0), 0).unwrap();
+        assert_eq!(tree.get_union((0.0, 6.0), false, true).unwrap().intervals, vec![(3.0, 5.0)]);
+        assert_eq!(tree.segment_interval(0), Some((3.0, 5.0)));
+        assert_eq!(tree.root_stats().max_ovp, 1);
+        assert!(tree.upsert((1.0, f64::INFINITY), 0).is_err());
+        assert_eq!(tree.segment_interval(0), Some((3.0, 5.0)));
+    }
+
+    #[test]
+    fn test_ids_by_length() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 10.0]).unwrap();
+        assert!(tree.ids_by_length().is_empty());
         tree.add_segment((2.0, 3.0), 1).unwrap();
-        assert_eq!(tree.root_stats().max_ovp, 2);
-        assert_eq!(tree.root_stats().length, 2.0);
+        tree.add_segment((1.0, 10.0), 0).unwrap();
+        tree.add_segment((1.0, 2.0), 2).unwrap();
+        assert_eq!(tree.ids_by_length(), vec![(0, 9.0), (1, 1.0), (2, 1.0)]);
     }
 
+    #[cfg(feature = "rayon")]
     #[test]
-    fn test_union() {
-        let mut tree = SegmentTree::new(vec![1.0, 2.0, 2.5, 3.0, 5.0]).unwrap();
+    fn test_parallel_build() {
+        let mut state = 0x2545f4914f6cdd1du64;
+        for n_ends in [0, 1, 2, 3, 7, 100, 1000, 5000] {
+            let ends: Vec<f64> = (0..n_ends).map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 11) as f64 / (1u64 << 53) as f64 * 1e6 - 5e5
+            }).collect();
+            let built = SegmentTree::new(ends.clone()).unwrap();
+            let mut sorted = ends;
+            sorted.sort_by(f64::total_cmp);
+            sorted.dedup();
+            assert_eq!(built.endpoints(), sorted);
+            // same leaves, internal bounds cleared and refilled by each pass
+            let reset = || {
+                let mut tree = SegmentTree::new(sorted.clone()).unwrap();
+                let n_internal = tree.nodes.len() >> 1;
+                for node in tree.nodes[..n_internal].iter_mut() {
+                    node.itv = (0.0, 0.0);
+                }
+                tree
+            };
+            let (mut serial, mut parallel) = (reset(), reset());
+            serial.set_internal_bounds_seq();
+            parallel.set_internal_bounds_par();
+            let bits = |tree: &SegmentTree| -> Vec<(u64, u64)> {
+                tree.nodes.iter().map(|node| (node.itv.0.to_bits(), node.itv.1.to_bits())).collect()
+            };
+            assert_eq!(bits(&parallel), bits(&serial));
+            assert_eq!(bits(&built), bits(&serial));
+        }
+    }
+
+    #[test]
+    fn test_get_unions() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 5.0, 6.0]).unwrap();
         tree.add_segment((1.0, 2.0), 0).unwrap();
-        tree.add_segment((2.5, 3.0), 1).unwrap();
-        tree.add_segment((3.0, 5.0), 2).unwrap();
+        tree.add_segment((5.0, 6.0), 1).unwrap();
+        let unions = tree.get_unions(&[(0.0, 3.0), (4.0, 5.5)]).unwrap();
+        assert_eq!(unions.len(), 2);
+        assert_eq!(unions[0].intervals, vec![(1.0, 2.0)]);
+        assert_eq!(unions[1].intervals, vec![(5.0, 5.5)]);
+        assert!(tree.get_unions(&[(0.0, 3.0), (4.0, f64::NAN)]).is_err());
+        assert!(tree.get_unions(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_overlapping_pair_count() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert_eq!(tree.overlapping_pair_count(), 0);
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((2.0, 5.0), 1).unwrap();
+        tree.add_segment((3.0, 6.0), 2).unwrap();
+        assert_eq!(tree.overlapping_pair_count(), 3);
+        tree.clear();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        tree.add_segment((4.0, 6.0), 2).unwrap();
+        assert_eq!(tree.overlapping_pair_count(), 0);
+        tree.add_segment((5.0, 5.0), 3).unwrap();
+        tree.add_segment((1.0, 6.0), 4).unwrap();
+        // point segments at a start, at an end, and twice at the same point
+        tree.add_segment((2.0, 2.0), 5).unwrap();
+        tree.add_segment((4.0, 4.0), 6).unwrap();
+        tree.add_segment((4.0, 4.0), 7).unwrap();
+        let n = 8;
+        let originals: Vec<(f64, f64)> = (0..n).map(|id| tree.segment_interval(id).unwrap()).collect();
+        let pairs: Vec<(usize, usize)> = (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))).collect();
+        let brute = pairs.iter().filter(|&&(i, j)| originals[i].shares_point(originals[j])).count();
+        // the brute force agrees with the stored (start, end] leaves
+        assert!(pairs.iter().all(|&(i, j)| originals[i].shares_point(originals[j]) == tree.ids_overlap(i, j)));
+        assert!(tree.ids_overlap(0, 5) && !tree.ids_overlap(1, 5) && tree.ids_overlap(6, 7));
+        assert_eq!(tree.overlapping_pair_count(), brute);
+    }
+
+    #[test]
+    fn test_max_non_overlapping() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert!(tree.max_non_overlapping().is_empty());
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 4.0), 1).unwrap();
+        tree.add_segment((5.0, 6.0), 2).unwrap();
+        let picked = tree.max_non_overlapping();
+        assert_eq!(picked.len(), 2);
+        assert_eq!(picked, vec![0, 2]);
+        tree.add_segment((3.0, 5.0), 3).unwrap();
+        assert_eq!(tree.max_non_overlapping(), vec![0, 3, 2]);
+        // (3, 3) and (6, 6) sit on picked ends, (1, 1) only touches the start of (1, 3]
+        tree.add_segment((3.0, 3.0), 4).unwrap();
+        tree.add_segment((6.0, 6.0), 5).unwrap();
+        tree.add_segment((1.0, 1.0), 6).unwrap();
+        let picked = tree.max_non_overlapping();
+        assert_eq!(picked, vec![6, 0, 3, 2]);
+        assert!(picked.iter().all(|&a| picked.iter().all(|&b| a == b || !tree.ids_overlap(a, b))));
+    }
+
+    #[test]
+    fn test_is_empty() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        assert!(tree.is_empty());
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        assert!(!tree.is_empty());
+        assert_eq!(tree.storage_count(), tree.nodes.iter().map(|node| node.segs.len()).sum());
+        tree.remove_segment((1.0, 3.0), 0).unwrap();
+        assert!(tree.is_empty());
+        tree.add_segment_deferred((2.0, 3.0), 1).unwrap();
+        assert!(!tree.is_empty());
+        let snap = tree.snapshot();
+        tree.clear();
+        assert!(tree.is_empty());
+        tree.restore(snap);
+        assert!(!tree.is_empty());
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.add_segment_unbounded((3.0, f64::INFINITY), 1).unwrap();
+        tree.translate(10.0).unwrap();
+        assert_eq!(tree.endpoints(), vec![11.0, 12.0, 13.0]);
+        assert_eq!(tree.get_union((0.0, 12.5), false, true).unwrap().intervals, vec![(11.0, 12.0)]);
+        assert_eq!(tree.segment_interval(0), Some((11.0, 12.0)));
+        assert_eq!(tree.segment_interval(1), Some((13.0, f64::INFINITY)));
+        assert_eq!(tree.root_stats().length, f64::INFINITY);
+        tree.remove_segment((11.0, 12.0), 0).unwrap();
+        assert_eq!(tree.get_union((0.0, 12.5), false, true).unwrap().intervals, vec![]);
+        assert!(matches!(tree.translate(f64::NAN), Err(InputError::ContainsNaN)));
+        assert!(matches!(tree.translate(f64::MAX), Err(InputError::UnsortedEndpoints)));
+        assert_eq!(tree.endpoints(), vec![11.0, 12.0, 13.0]);
+    }
+
+    #[test]
+    fn test_scale() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0]).unwrap();
+        tree.add_segment((1.0, 2.0), 0).unwrap();
+        tree.scale(2.0).unwrap();
+        assert_eq!(tree.endpoints(), vec![2.0, 4.0, 6.0]);
+        assert_eq!(tree.get_union((0.0, 5.0), false, true).unwrap().intervals, vec![(2.0, 4.0)]);
+        assert_eq!(tree.segment_interval(0), Some((2.0, 4.0)));
+        assert_eq!(tree.covered_length(), 2.0);
+        assert!(matches!(tree.scale(0.0), Err(InputError::NonPositiveFactor)));
+        assert!(matches!(tree.scale(-1.0), Err(InputError::NonPositiveFactor)));
+        assert!(matches!(tree.scale(f64::INFINITY), Err(InputError::ContainsInfinite)));
+        assert!(matches!(tree.scale(f64::MAX), Err(InputError::ContainsInfinite)));
+        assert_eq!(tree.endpoints(), vec![2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn test_coverage_rle() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        assert_eq!(tree.coverage_rle((0.0, 5.0)).unwrap(), vec![(0.0, 5.0, 0)]);
+        tree.add_segment((1.0, 4.0), 0).unwrap();
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert_eq!(tree.coverage_rle((1.0, 4.0)).unwrap(), vec![(1.0, 2.0, 1), (2.0, 3.0, 2), (3.0, 4.0, 1)]);
+        let all = tree.coverage_rle((f64::NEG_INFINITY, f64::INFINITY)).unwrap();
+        assert_eq!(all.first(), Some(&(f64::NEG_INFINITY, 1.0, 0)));
+        assert_eq!(all.last(), Some(&(4.0, f64::INFINITY, 0)));
+        assert_eq!(tree.coverage_rle((2.5, 2.5)).unwrap(), vec![(2.5, 2.5, 2)]);
+        assert_eq!(tree.coverage_rle((5.0, 5.0)).unwrap(), vec![(5.0, 5.0, 0)]);
+        assert!(matches!(tree.coverage_rle((2.0, 1.0)), Err(InputError::StartGreaterThanEnd)));
+    }
+
+    #[test]
+    fn test_clip() {
+        let mut tree = SegmentTree::new(vec![0.0, 10.0, 20.0]).unwrap();
+        tree.add_segment((0.0, 10.0), 0).unwrap();
+        tree.add_segment((15.0, 20.0), 1).unwrap();
+        tree.clip((2.0, 5.0)).unwrap();
+        let all = (f64::NEG_INFINITY, f64::INFINITY);
+        assert_eq!(tree.get_union(all, false, true).unwrap().intervals, vec![(2.0, 5.0)]);
+        assert_eq!(tree.endpoints(), vec![0.0, 2.0, 5.0, 10.0, 20.0]);
+        assert_eq!(tree.segment_interval(0), Some((2.0, 5.0)));
+        assert_eq!(tree.segment_interval(1), None);
+        assert_eq!(tree.covered_length(), 3.0);
+        assert!(matches!(tree.clip((5.0, 2.0)), Err(InputError::StartGreaterThanEnd)));
+        // segments touching the bounds share no point with (2, 5], a point at its end does
+        let mut tree = SegmentTree::new(vec![0.0, 2.0, 5.0, 8.0]).unwrap();
+        tree.add_segment((0.0, 2.0), 0).unwrap();
+        tree.add_segment((5.0, 8.0), 1).unwrap();
+        tree.add_segment((2.0, 2.0), 2).unwrap();
+        tree.add_segment((5.0, 5.0), 3).unwrap();
+        tree.add_segment((0.0, 8.0), 4).unwrap();
+        tree.clip((2.0, 5.0)).unwrap();
+        let kept: Vec<Option<(f64, f64)>> = (0..5).map(|id| tree.segment_interval(id)).collect();
+        assert_eq!(kept, vec![None, None, None, Some((5.0, 5.0)), Some((2.0, 5.0))]);
+        assert_eq!(tree.depth_at_point(5.0).unwrap(), 2);
+        assert_eq!(tree.depth_at_point(2.0).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_coverage_profile() {
+        let mut tree = SegmentTree::new(vec![0.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment((0.0, 2.0), 0).unwrap();
+        assert_eq!(tree.coverage_profile((0.0, 4.0), 4).unwrap(), vec![1.0, 1.0, 0.0, 0.0]);
+        tree.add_segment((2.0, 3.0), 1).unwrap();
+        assert_eq!(tree.coverage_profile((0.0, 4.0), 2).unwrap(), vec![2.0, 1.0]);
+        assert_eq!(tree.coverage_profile((1.0, 4.0), 3).unwrap(), vec![1.0, 1.0, 0.0]);
+        assert_eq!(tree.coverage_profile((1.0, 1.0), 2).unwrap(), vec![0.0, 0.0]);
+        assert!(matches!(tree.coverage_profile((0.0, 4.0), 0), Err(InputError::ZeroBins)));
+        assert!(matches!(tree.coverage_profile((0.0, 4.0), usize::MAX), Err(InputError::TooLarge)));
+        assert!(matches!(tree.coverage_profile((0.0, 4.0), isize::MAX as usize / 8 + 1), Err(InputError::TooLarge)));
+        assert!(matches!(tree.coverage_profile((0.0, f64::INFINITY), 2), Err(InputError::ContainsInfinite)));
+    }
+
+    #[test]
+    fn test_is_endpoint() {
+        let tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 0.0]).unwrap();
+        assert!(tree.is_endpoint(2.0));
+        assert!(!tree.is_endpoint(2.5));
+        assert!(tree.is_endpoint(-0.0));
+        assert!(!tree.is_endpoint(f64::NAN));
+        assert!(!tree.is_endpoint(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_surrounding_endpoints() {
+        let tree = SegmentTree::new(vec![1.0, 3.0, 5.0]).unwrap();
+        assert_eq!(tree.surrounding_endpoints(4.0), (Some(3.0), Some(5.0)));
+        assert_eq!(tree.surrounding_endpoints(3.0), (Some(3.0), Some(3.0)));
+        assert_eq!(tree.surrounding_endpoints(0.0), (None, Some(1.0)));
+        assert_eq!(tree.surrounding_endpoints(f64::INFINITY), (Some(5.0), None));
+        assert_eq!(tree.surrounding_endpoints(f64::NAN), (None, None));
+        // a value that went through f32 lands next to the endpoint and snaps onto it
+        let tree = SegmentTree::new_with_epsilon(vec![0.1, 0.3, 0.5], 1e-6).unwrap();
+        let rounded = 0.3f32 as f64;
+        assert_ne!(rounded, 0.3);
+        assert!(tree.is_endpoint(rounded));
+        assert_eq!(tree.surrounding_endpoints(rounded), (Some(0.3), Some(0.3)));
+        assert_eq!(tree.surrounding_endpoints(0.1 - 1e-9), (Some(0.1), Some(0.1)));
+        assert_eq!(tree.surrounding_endpoints(0.4), (Some(0.3), Some(0.5)));
+        assert_eq!(tree.surrounding_endpoints(f64::INFINITY), (Some(0.5), None));
+    }
+
+    #[test]
+    fn test_remove_segments() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        let segments = [((1.0, 3.0), 0), ((2.0, 4.0), 1), ((2.0, 3.0), 2), ((1.0, 4.0), 3)];
+        for &(interval, id) in &segments {
+            tree.add_segment(interval, id).unwrap();
+        }
+        assert_eq!(tree.root_stats().max_ovp, 4);
+        tree.remove_segments(&segments[..3]).unwrap();
         assert_eq!(tree.root_stats().max_ovp, 1);
-        assert_eq!(tree.root_stats().length, 3.5);
-        print_tree(&tree);
-        let _union = tree.get_union((f64::NEG_INFINITY, f64::INFINITY)).unwrap();
-        assert_eq!(_union.intervals, vec![(1.0, 2.0), (2.5, 5.0)]);
-        assert!(_union.contains_point(1.5));
-        assert!(!_union.contains_point(2.3));
-        assert!(_union.contains_point(4.0));
-        assert!(!_union.contains_point(f64::INFINITY));
-        assert!(!_union.contains_point(f64::NAN));
+        assert_eq!(tree.segment_interval(0), None);
+        assert_eq!(tree.segment_interval(3), Some((1.0, 4.0)));
+        assert!(matches!(tree.remove_segments(&[((1.0, 4.0), 3), ((2.0, f64::NAN), 4)]), Err(InputError::ContainsNaN)));
+        assert_eq!(tree.root_stats().max_ovp, 1);
+        tree.remove_segments(&segments[3..]).unwrap();
+        assert!(tree.is_empty());
+        assert_eq!(tree.root_stats().max_ovp, 0);
+    }
+
+    #[test]
+    fn test_height() {
+        assert_eq!(SegmentTree::new(vec![]).unwrap().height(), 0);
+        let mut last = 0;
+        for n_ends in [1u32, 2, 4, 16, 100, 1000] {
+            let tree = SegmentTree::new((0..n_ends).map(f64::from).collect()).unwrap();
+            assert_eq!(tree.height(), tree.node_count().ilog2() as usize);
+            assert!(tree.height().abs_diff(n_ends.ilog2() as usize + 2) <= 1);
+            assert!(tree.height() >= last);
+            last = tree.height();
+        }
+    }
+
+    #[test]
+    fn test_is_disjoint() {
+        let ends = vec![1.0, 2.0, 3.0, 4.0];
+        let (mut a, mut b) = (SegmentTree::new(ends.clone()).unwrap(), SegmentTree::new(ends).unwrap());
+        assert!(a.is_disjoint(&b).unwrap());
+        a.add_segment((1.0, 2.0), 0).unwrap();
+        b.add_segment((3.0, 4.0), 0).unwrap();
+        assert!(a.is_disjoint(&b).unwrap());
+        b.add_segment((2.0, 3.0), 1).unwrap();
+        assert!(a.is_disjoint(&b).unwrap());
+        a.clear();
+        b.clear();
+        a.add_segment((1.0, 3.0), 0).unwrap();
+        b.add_segment((2.0, 4.0), 0).unwrap();
+        assert!(!a.is_disjoint(&b).unwrap());
+        assert!(!b.is_disjoint(&a).unwrap());
+        // a point segment inside the other coverage shares no length
+        a.clear();
+        a.add_segment((3.0, 3.0), 0).unwrap();
+        a.add_segment((1.0, 1.0), 1).unwrap();
+        assert!(b.depth_at_point(3.0).unwrap() == 1 && a.depth_at_point(3.0).unwrap() == 1);
+        assert!(a.is_disjoint(&b).unwrap());
+        a.add_segment((1.0, 3.0), 2).unwrap();
+        assert!(!a.is_disjoint(&b).unwrap());
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        assert_eq!(SegmentTree::new(vec![]).unwrap().check_invariants(), Ok(()));
+        let mut tree = SegmentTree::new((0..37).map(f64::from).collect()).unwrap();
+        assert_eq!(tree.check_invariants(), Ok(()));
+        tree.add_segment((3.0, 20.0), 0).unwrap();
+        tree.add_segment_unbounded((10.0, f64::INFINITY), 1).unwrap();
+        assert_eq!(tree.check_invariants(), Ok(()));
+        let copy = || {
+            let (ends, storage, originals, lazy, epsilon) = tree.to_state();
+            SegmentTree::from_state(ends, storage, originals, lazy, epsilon).unwrap()
+        };
+        assert_eq!(copy().check_invariants(), Ok(()));
+        let mut bad_bounds = copy();
+        bad_bounds.nodes[5].itv.0 = -1.0;
+        assert!(bad_bounds.check_invariants().is_err());
+        let mut bad_leaf = copy();
+        let leaf = bad_leaf.leaves_in_order().nth(3).unwrap();
+        bad_leaf.nodes[leaf].itv.1 += 0.5;
+        assert!(bad_leaf.check_invariants().is_err());
+        let mut bad_stats = copy();
+        bad_stats.stats[0].max_ovp += 1;
+        assert!(bad_stats.check_invariants().is_err());
+        let mut stale = copy();
+        stale.add_segment_deferred((1.0, 2.0), 2).unwrap();
+        assert!(stale.check_invariants().is_err());
+        stale.recompute_stats();
+        assert_eq!(stale.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_epsilon_snapping() {
+        let mut tree = SegmentTree::new_with_epsilon(vec![1.0, 1.0 + 1e-12, 2.0, 3.0], 1e-9).unwrap();
+        tree.add_segment((1.0 + 1e-12, 2.0), 0).unwrap();
+        assert_eq!(tree.get_union((0.0, 4.0), false, true).unwrap().intervals, vec![(1.0, 2.0)]);
+        assert_eq!(tree.segment_interval(0), Some((1.0, 2.0)));
+        assert_eq!(tree.depth_at_point(2.0 - 1e-12).unwrap(), 1);
+        // snapped onto the open start of (1, 2]
+        assert_eq!(tree.depth_at_point(1.0 + 1e-12).unwrap(), 0);
+        assert!(tree.is_endpoint(1.0 + 1e-12));
+        // snapped from either side, onto the nearest endpoint
+        tree.add_segment((2.0 + 1e-10, 3.0 - 1e-10), 1).unwrap();
+        assert_eq!(tree.segment_interval(1), Some((2.0, 3.0)));
+        assert_eq!(tree.get_union((0.0, 4.0), false, true).unwrap().intervals, vec![(1.0, 3.0)]);
+        assert_eq!(tree.depth_at_point(3.0 + 1e-10).unwrap(), 1);
+        // values farther than epsilon are left alone
+        assert_eq!(tree.depth_at_point(1.5).unwrap(), 1);
+        assert!(!tree.is_endpoint(1.0 + 1e-6));
+        tree.remove_segment((1.0 + 1e-12, 2.0), 0).unwrap();
+        assert_eq!(tree.segment_interval(0), None);
+        assert_eq!(tree.get_union((0.0, 4.0), false, true).unwrap().intervals, vec![(2.0, 3.0)]);
+        // epsilon survives a state round trip and scales with the coordinates
+        let (ends, storage, originals, lazy, epsilon) = tree.to_state();
+        assert_eq!(SegmentTree::from_state(ends, storage, originals, lazy, epsilon).unwrap(), tree);
+        tree.scale(2.0).unwrap();
+        assert_eq!(tree.depth_at_point(6.0 + 1.5e-9).unwrap(), 1);
+        let mut exact = SegmentTree::new(vec![1.0, 2.0]).unwrap();
+        exact.add_segment((1.0, 2.0), 0).unwrap();
+        assert_eq!(exact.depth_at_point(2.0 + 1e-12).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_lazy_rebuild_keeps_storage() {
+        let probes = [0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0, 4.5];
+        let depths = |tree: &SegmentTree| probes.map(|x| tree.depth_at_point(x).unwrap());
+
+        // the same id stored twice keeps both copies
+        let mut tree = SegmentTree::new_lazy();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 4.0), 0).unwrap();
+        let before = depths(&tree);
+        assert_eq!(before, [0, 0, 1, 1, 2, 2, 1, 1, 0]);
+        tree.add_segment((10.0, 11.0), 1).unwrap();
+        assert_eq!(depths(&tree), before);
+        assert_eq!(tree.root_stats().max_ovp, 2);
+
+        // a partial removal is not undone by the next rebuild
+        let mut tree = SegmentTree::new_lazy();
+        tree.add_segment((1.0, 3.0), 0).unwrap();
+        tree.add_segment((2.0, 2.0), 9).unwrap();
+        tree.remove_segment((1.0, 2.0), 0).unwrap();
+        let before = depths(&tree);
+        assert_eq!(before, [0, 0, 0, 1, 1, 1, 0, 0, 0]);
+        tree.add_segment((10.0, 11.0), 1).unwrap();
+        assert_eq!(depths(&tree), before);
+    }
+
+    #[test]
+    fn test_deferred_then_query() {
+        let mut tree = SegmentTree::new(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        tree.add_segment_deferred((1.0, 3.0), 0).unwrap();
+        tree.add_segment_deferred((2.0, 4.0), 1).unwrap();
+        // stale until recomputed, but every reader of the stats stays in bounds
+        assert_eq!(tree.root_stats().max_ovp, 0);
+        assert_eq!(tree.depth_histogram(), vec![0.0, 2.0, 1.0]);
+        assert!(tree.peak_intervals().intervals.is_empty());
+        assert!(tree.regions_at_least(1).intervals.is_empty());
+        assert!(tree.get_union((0.0, 5.0), false, true).unwrap().intervals.is_empty());
+        assert_eq!(tree.depth_range((0.0, 5.0)).unwrap(), (0, 0));
+        assert_eq!(tree.depth_at_point(2.5).unwrap(), 2);
+        tree.recompute_stats();
+        assert_eq!(tree.depth_histogram(), vec![0.0, 2.0, 1.0]);
+        assert_eq!(tree.peak_intervals().intervals, vec![(2.0, 3.0)]);
     }
 }
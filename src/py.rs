@@ -1,16 +1,21 @@
 pub use pyo3::prelude::*;
-use pyo3::exceptions::PyValueError;
-use pyo3::types::PyDict;
+use pyo3::exceptions::{PyAssertionError, PyOverflowError, PyRuntimeError, PyValueError};
+use pyo3::types::{PyBool, PyDict};
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1};
+use numpy::ndarray::Array2;
 use crate::core;
 
-#[pyclass]
+#[pyclass(module = "pysegtree.segtree_native")]
 struct SegmentTree {
     inner: core::SegmentTree,
 }
 
 impl From<core::InputError> for PyErr {
     fn from(e: core::InputError) -> Self {
-        PyValueError::new_err(e.to_string())
+        match e {
+            core::InputError::TooLarge => PyOverflowError::new_err(e.to_string()),
+            _ => PyValueError::new_err(e.to_string()),
+        }
     }
 }       
 
@@ -24,50 +29,546 @@ impl IntoPy<PyObject> for core::Stats {
     }
 }
 
-#[pyclass]
+#[pyclass(module = "pysegtree.segtree_native")]
 struct Union{
     inner: core::Union
 }
 
 #[pymethods]
 impl Union {
+    #[getter]
+    fn intervals(&self) -> Vec<(f64, f64)> {
+        self.inner.intervals.clone()
+    }
+
     fn contains_point(&self, query: f64) -> bool {
         self.inner.contains_point(query)
     }
+
+    fn try_contains_point(&self, query: f64) -> PyResult<bool> {
+        Ok(self.inner.try_contains_point(query)?)
+    }
+
+    fn contains_interval(&self, query: (f64, f64)) -> bool {
+        self.inner.contains_interval(query)
+    }
+
+    fn next_interval(&self, from: f64) -> PyResult<Option<(f64, f64)>> {
+        Ok(self.inner.next_interval(from)?)
+    }
+}
+
+#[pyclass(module = "pysegtree.segtree_native")]
+struct DepthUnion{
+    inner: core::DepthUnion
+}
+
+#[pymethods]
+impl DepthUnion {
+    fn depth_at(&self, query: f64) -> usize {
+        self.inner.depth_at(query)
+    }
+}
+
+#[pyclass(module = "pysegtree.segtree_native")]
+struct TreeSnapshot{
+    inner: core::TreeSnapshot
+}
+
+#[pyclass(module = "pysegtree.segtree_native")]
+struct SegmentTreeBuilder{
+    inner: Option<core::SegmentTreeBuilder> // None once finished
+}
+
+fn builder_finished() -> PyErr {
+    PyRuntimeError::new_err("builder already finished")
+}
+
+#[pymethods]
+impl SegmentTreeBuilder {
+    #[new]
+    fn new() -> Self {
+        Self { inner: Some(core::SegmentTreeBuilder::default()) }
+    }
+
+    fn push_end(&mut self, end: f64) -> PyResult<()> {
+        self.inner.as_mut().ok_or_else(builder_finished)?.push_end(end)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> PyResult<SegmentTree> {
+        let builder = self.inner.take().ok_or_else(builder_finished)?;
+        Ok(SegmentTree { inner: builder.finish()? })
+    }
+}
+
+const REPR_MAX_LEN: usize = 2000;
+
+type NodeArrays<'py> = (Bound<'py, PyArray1<f64>>, Bound<'py, PyArray1<f64>>, Bound<'py, PyArray1<usize>>);
+
+// (N, 2) array with one (start, end) row per interval
+fn intervals_array<'py>(py: Python<'py>, intervals: &[(f64, f64)]) -> Bound<'py, PyArray2<f64>> {
+    let flat: Vec<f64> = intervals.iter().flat_map(|&(start, end)| [start, end]).collect();
+    Array2::from_shape_vec((intervals.len(), 2), flat).unwrap().into_pyarray_bound(py)
 }
 
 #[pymethods]
 impl SegmentTree {
 
+    /// `epsilon` only applies when `dedup` is on; with `dedup=False` repeated endpoints are kept.
+    /// Segment and query bounds within `epsilon` of an endpoint snap onto the nearest one.
     #[new]
-    fn new(mut all_ends: Vec<f64>) -> PyResult<Self> {
+    #[pyo3(signature = (all_ends, epsilon=0.0, dedup=true))]
+    fn new(all_ends: Vec<f64>, epsilon: f64, dedup: bool) -> PyResult<Self> {
+        let inner = if dedup {
+            core::SegmentTree::new_with_epsilon(all_ends, epsilon)?
+        } else {
+            core::SegmentTree::new_with_options(all_ends, false)?
+        };
+        Ok(Self { inner })
+    }
+
+    #[staticmethod]
+    fn new_lazy() -> Self {
+        Self {
+            inner: core::SegmentTree::new_lazy()
+        }
+    }
+
+    #[staticmethod]
+    fn new_sorted(all_ends: Vec<f64>) -> PyResult<Self> {
+        Ok(Self {
+            inner: core::SegmentTree::new_sorted(all_ends)?
+        })
+    }
+
+    #[staticmethod]
+    fn from_array(all_ends: PyReadonlyArray1<f64>) -> PyResult<Self> {
         Ok(Self {
-            inner: core::SegmentTree::new(all_ends)?
+            inner: core::SegmentTree::new(all_ends.as_array().to_vec())?
         })
     }
+
+    #[staticmethod]
+    fn from_segments(segments: Vec<((f64, f64), usize)>) -> PyResult<Self> {
+        Ok(Self {
+            inner: core::SegmentTree::from_segments(&segments)?
+        })
+    }
+
+    /// Ids default to 0, 1, 2, ... in the order of `intervals`.
+    #[staticmethod]
+    #[pyo3(signature = (intervals, ids=None))]
+    fn from_intervals(intervals: Vec<(f64, f64)>, ids: Option<Vec<usize>>) -> PyResult<Self> {
+        let ids = ids.unwrap_or_else(|| (0..intervals.len()).collect());
+        if ids.len() != intervals.len() {
+            return Err(PyValueError::new_err("intervals and ids differ in length"));
+        }
+        let segments: Vec<((f64, f64), usize)> = intervals.into_iter().zip(ids).collect();
+        Self::from_segments(segments)
+    }
     
+    fn __repr__(&self) -> String {
+        let mut repr = self.inner.dump();
+        if repr.len() > REPR_MAX_LEN {
+            repr.truncate(REPR_MAX_LEN);
+            repr.push_str("...");
+        }
+        repr
+    }
+
     fn add_segment(&mut self, interval: (f64, f64), id: usize) -> PyResult<()> {
         self.inner.add_segment(interval, id)?;
         Ok(())
     }
     
+    fn add_segment_unbounded(&mut self, interval: (f64, f64), id: usize) -> PyResult<()> {
+        self.inner.add_segment_unbounded(interval, id)?;
+        Ok(())
+    }
+
+    fn add_segment_deferred(&mut self, interval: (f64, f64), id: usize) -> PyResult<()> {
+        self.inner.add_segment_deferred(interval, id)?;
+        Ok(())
+    }
+
+    fn upsert(&mut self, interval: (f64, f64), id: usize) -> PyResult<()> {
+        self.inner.upsert(interval, id)?;
+        Ok(())
+    }
+
+    fn recompute_stats(&mut self) {
+        self.inner.recompute_stats()
+    }
+
     fn remove_segment(&mut self, interval: (f64, f64), id: usize) -> PyResult<()> {
         self.inner.remove_segment(interval, id)?;
         Ok(())
     }
 
-    fn get_union(&self, interval: (f64, f64)) -> PyResult<Union> {
-        Ok(Union { inner: self.inner.get_union(interval)? })
+    fn remove(&mut self, id: usize) -> PyResult<bool> {
+        Ok(self.inner.remove(id)?)
+    }
+
+    fn remove_segments(&mut self, intervals: Vec<(f64, f64)>, ids: Vec<usize>) -> PyResult<()> {
+        if intervals.len() != ids.len() {
+            return Err(PyValueError::new_err("intervals and ids differ in length"));
+        }
+        let segments: Vec<((f64, f64), usize)> = intervals.into_iter().zip(ids).collect();
+        self.inner.remove_segments(&segments)?;
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    fn __getnewargs__(&self) -> (Vec<f64>,) {
+        (self.inner.endpoints(),)
+    }
+
+    fn __getstate__(&self) -> core::TreeState {
+        self.inner.to_state()
+    }
+
+    fn __setstate__(&mut self, state: core::TreeState) -> PyResult<()> {
+        self.inner = core::SegmentTree::from_state(state.0, state.1, state.2, state.3, state.4)?;
+        Ok(())
+    }
+
+    fn __eq__(&self, other: PyRef<SegmentTree>) -> bool {
+        self.inner == other.inner
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __exit__(&mut self, _exc_type: &Bound<PyAny>, _exc_value: &Bound<PyAny>, _traceback: &Bound<PyAny>) -> bool {
+        self.inner.clear();
+        false
+    }
+
+    fn relabel(&mut self, id: usize, new_id: usize) -> PyResult<()> {
+        self.inner.relabel(id, new_id)?;
+        Ok(())
+    }
+
+    fn translate(&mut self, delta: f64) -> PyResult<()> {
+        self.inner.translate(delta)?;
+        Ok(())
+    }
+
+    fn scale(&mut self, factor: f64) -> PyResult<()> {
+        self.inner.scale(factor)?;
+        Ok(())
+    }
+
+    fn clip(&mut self, bounds: (f64, f64)) -> PyResult<()> {
+        self.inner.clip(bounds)?;
+        Ok(())
+    }
+
+    fn merge(&mut self, other: PyRef<SegmentTree>) -> PyResult<()> {
+        self.inner.merge(&other.inner)?;
+        Ok(())
+    }
+
+    fn snapshot(&self) -> TreeSnapshot {
+        TreeSnapshot { inner: self.inner.snapshot() }
+    }
+
+    fn restore(&mut self, snap: PyRef<TreeSnapshot>) {
+        self.inner.restore(snap.inner.clone())
+    }
+
+    fn jaccard(&self, other: PyRef<SegmentTree>) -> PyResult<f64> {
+        Ok(self.inner.jaccard(&other.inner)?)
+    }
+
+    fn is_disjoint(&self, other: PyRef<SegmentTree>) -> PyResult<bool> {
+        Ok(self.inner.is_disjoint(&other.inner)?)
+    }
+
+    fn symmetric_difference(&self, other: PyRef<SegmentTree>) -> PyResult<Union> {
+        Ok(Union { inner: self.inner.symmetric_difference(&other.inner)? })
+    }
+
+    fn subtree(&self, interval: (f64, f64)) -> PyResult<SegmentTree> {
+        Ok(SegmentTree { inner: self.inner.subtree(interval)? })
+    }
+
+    #[pyo3(signature = (interval, include_boundary=false, coalesce=true))]
+    fn get_union(&self, interval: (f64, f64), include_boundary: bool, coalesce: bool) -> PyResult<Union> {
+        Ok(Union { inner: self.inner.get_union(interval, include_boundary, coalesce)? })
+    }
+
+    #[pyo3(signature = (interval, min_depth=1))]
+    fn get_union_min_depth(&self, interval: (f64, f64), min_depth: usize) -> PyResult<Union> {
+        Ok(Union { inner: self.inner.get_union_min_depth(interval, min_depth)? })
+    }
+
+    fn get_unions(&self, intervals: Vec<(f64, f64)>) -> PyResult<Vec<Union>> {
+        let unions = self.inner.get_unions(&intervals)?;
+        Ok(unions.into_iter().map(|inner| Union { inner }).collect())
+    }
+
+    #[pyo3(signature = (interval, include_boundary=false))]
+    fn get_union_array<'py>(&self, py: Python<'py>, interval: (f64, f64), include_boundary: bool) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        let union = self.inner.get_union(interval, include_boundary, true)?;
+        Ok(intervals_array(py, &union.intervals))
+    }
+
+    /// Calls `callback(start, end)` for each merged covered interval. Only returning `False` itself
+    /// stops early; any other value, falsy ones like `None` or `0` included, continues.
+    fn get_union_foreach(&self, interval: (f64, f64), callback: &Bound<PyAny>) -> PyResult<()> {
+        let mut err = None;
+        self.inner.visit_union(interval, |itv| match callback.call1(itv) {
+            Ok(ret) => ret.downcast::<PyBool>().map_or(true, |ret| ret.is_true()),
+            Err(e) => {
+                err = Some(e);
+                false
+            }
+        })?;
+        err.map_or(Ok(()), Err)
+    }
+
+    fn get_union_with_depth(&self, interval: (f64, f64)) -> PyResult<DepthUnion> {
+        Ok(DepthUnion { inner: self.inner.get_union_with_depth(interval)? })
+    }
+
+    fn coverage_rle(&self, interval: (f64, f64)) -> PyResult<Vec<(f64, f64, usize)>> {
+        Ok(self.inner.coverage_rle(interval)?)
+    }
+
+    fn depth_transitions(&self, interval: (f64, f64)) -> PyResult<Vec<(f64, i64)>> {
+        Ok(self.inner.depth_transitions(interval)?)
+    }
+
+    fn breakpoints(&self, interval: (f64, f64)) -> PyResult<Vec<f64>> {
+        Ok(self.inner.breakpoints(interval)?)
+    }
+
+    fn coverage_integral(&self, interval: (f64, f64)) -> PyResult<f64> {
+        Ok(self.inner.coverage_integral(interval)?)
+    }
+
+    fn get_gaps(&self, interval: (f64, f64)) -> PyResult<Union> {
+        Ok(Union { inner: self.inner.get_gaps(interval)? })
+    }
+
+    fn get_gaps_array<'py>(&self, py: Python<'py>, interval: (f64, f64)) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        let gaps = self.inner.get_gaps(interval)?;
+        Ok(intervals_array(py, &gaps.intervals))
+    }
+
+    fn gap_lengths(&self, interval: (f64, f64)) -> PyResult<Vec<f64>> {
+        Ok(self.inner.gap_lengths(interval)?)
+    }
+
+    fn coverage_profile(&self, interval: (f64, f64), bins: usize) -> PyResult<Vec<f64>> {
+        Ok(self.inner.coverage_profile(interval, bins)?)
+    }
+
+    fn longest_covered(&self, interval: (f64, f64)) -> PyResult<Option<(f64, f64)>> {
+        Ok(self.inner.longest_covered(interval)?)
+    }
+
+    fn component_count(&self, interval: (f64, f64)) -> PyResult<usize> {
+        Ok(self.inner.component_count(interval)?)
+    }
+
+    fn covered_length_before(&self, point: f64) -> PyResult<f64> {
+        Ok(self.inner.covered_length_before(point)?)
+    }
+
+    fn coverage_split(&self, at: f64) -> PyResult<(Union, Union)> {
+        let (left, right) = self.inner.coverage_split(at)?;
+        Ok((Union { inner: left }, Union { inner: right }))
+    }
+
+    fn segments_in(&self, interval: (f64, f64)) -> PyResult<Vec<usize>> {
+        Ok(self.inner.segments_in(interval)?)
+    }
+
+    fn segment_interval(&self, id: usize) -> Option<(f64, f64)> {
+        self.inner.segment_interval(id)
+    }
+
+    fn ids_by_length(&self) -> Vec<(usize, f64)> {
+        self.inner.ids_by_length()
+    }
+
+    fn overlapping_pair_count(&self) -> usize {
+        self.inner.overlapping_pair_count()
+    }
+
+    fn max_non_overlapping(&self) -> Vec<usize> {
+        self.inner.max_non_overlapping()
+    }
+
+    fn contained_in(&self, interval: (f64, f64)) -> PyResult<Vec<usize>> {
+        Ok(self.inner.contained_in(interval)?)
+    }
+
+    fn distinct_overlap_count(&self, interval: (f64, f64)) -> PyResult<usize> {
+        Ok(self.inner.distinct_overlap_count(interval)?)
+    }
+
+    fn overlaps_any(&self, interval: (f64, f64)) -> PyResult<bool> {
+        Ok(self.inner.overlaps_any(interval)?)
+    }
+
+    fn ids_overlap(&self, a: usize, b: usize) -> bool {
+        self.inner.ids_overlap(a, b)
+    }
+
+    fn first_uncovered(&self, from: f64) -> PyResult<Option<f64>> {
+        Ok(self.inner.first_uncovered(from)?)
+    }
+
+    fn first_covered(&self, from: f64) -> PyResult<Option<f64>> {
+        Ok(self.inner.first_covered(from)?)
+    }
+
+    fn endpoints(&self) -> Vec<f64> {
+        self.inner.endpoints()
+    }
+
+    fn is_endpoint(&self, point: f64) -> bool {
+        self.inner.is_endpoint(point)
+    }
+
+    fn surrounding_endpoints(&self, point: f64) -> (Option<f64>, Option<f64>) {
+        self.inner.surrounding_endpoints(point)
+    }
+
+    fn depth_at_point(&self, query: f64) -> PyResult<usize> {
+        Ok(self.inner.depth_at_point(query)?)
+    }
+
+    /// `depth_at_point` for every entry of `points`, without a Python call per point.
+    fn overlap_counts<'py>(&self, py: Python<'py>, points: PyReadonlyArray1<f64>) -> PyResult<Bound<'py, PyArray1<usize>>> {
+        let depths = points.as_array().iter()
+            .map(|&point| self.inner.depth_at_point(point))
+            .collect::<Result<Vec<usize>, _>>()?;
+        Ok(depths.into_pyarray_bound(py))
+    }
+
+    fn depth_range(&self, interval: (f64, f64)) -> PyResult<(usize, usize)> {
+        Ok(self.inner.depth_range(interval)?)
+    }
+
+    fn endpoint_depths(&self) -> Vec<(f64, usize)> {
+        self.inner.endpoint_depths()
+    }
+
+    fn endpoint_utilization(&self) -> f64 {
+        self.inner.endpoint_utilization()
+    }
+
+    #[getter]
+    fn covered_length(&self) -> f64 {
+        self.inner.covered_length()
+    }
+
+    fn coverage_bounds(&self) -> Option<(f64, f64)> {
+        self.inner.coverage_bounds()
     }
 
     #[getter]
     fn root_stats(&self) -> core::Stats {
         self.inner.root_stats()
     }
+
+    fn all_stats(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        self.inner.all_stats().into_iter().map(|(itv, stats)| {
+            let d = stats.into_py(py);
+            d.bind(py).set_item("interval", itv)?;
+            Ok(d)
+        }).collect()
+    }
+
+    #[getter]
+    fn global_min_overlap(&self) -> usize {
+        self.inner.global_min_overlap()
+    }
+
+    #[getter]
+    fn global_max_overlap(&self) -> usize {
+        self.inner.global_max_overlap()
+    }
+
+    #[getter]
+    fn is_line_covered(&self) -> bool {
+        self.inner.is_line_covered()
+    }
+
+    fn depth_histogram(&self) -> Vec<f64> {
+        self.inner.depth_histogram()
+    }
+
+    #[getter]
+    fn memory_bytes(&self) -> usize {
+        self.inner.memory_bytes()
+    }
+
+    #[getter]
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn __bool__(&self) -> bool {
+        !self.inner.is_empty()
+    }
+
+    #[getter]
+    fn storage_count(&self) -> usize {
+        self.inner.storage_count()
+    }
+
+    #[getter]
+    fn node_count(&self) -> usize {
+        self.inner.node_count()
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.inner.height()
+    }
+
+    /// Raises `AssertionError` describing the first broken invariant.
+    fn check_invariants(&self) -> PyResult<()> {
+        self.inner.check_invariants().map_err(PyAssertionError::new_err)
+    }
+
+    fn to_arrays<'py>(&self, py: Python<'py>) -> NodeArrays<'py> {
+        let (starts, ends, counts) = self.inner.to_arrays();
+        (starts.into_pyarray_bound(py), ends.into_pyarray_bound(py), counts.into_pyarray_bound(py))
+    }
+
+    fn reserve_segments(&mut self, per_node: usize) {
+        self.inner.reserve_segments(per_node)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn peak_intervals(&self) -> Union {
+        Union { inner: self.inner.peak_intervals() }
+    }
+
+    fn regions_at_least(&self, k: usize) -> Union {
+        Union { inner: self.inner.regions_at_least(k) }
+    }
 }
 
 pub fn register(m: &Bound<PyModule>) -> PyResult<()> {
     m.add_class::<SegmentTree>()?;
     m.add_class::<Union>()?;
+    m.add_class::<DepthUnion>()?;
+    m.add_class::<TreeSnapshot>()?;
+    m.add_class::<SegmentTreeBuilder>()?;
     Ok(())
 }
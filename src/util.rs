@@ -10,6 +10,24 @@ pub enum InputError {
     ContainsInfinite,
     #[error("Invalid interval: start > end")]
     StartGreaterThanEnd,
+    #[error("Segment {0} is infinite, use add_segment_unbounded for open-ended segments")]
+    InfiniteSegmentEnd(&'static str),
+    #[error("Trees are built over different endpoints")]
+    EndpointsMismatch,
+    #[error("Segment id already present")]
+    DuplicateId,
+    #[error("Endpoints are not strictly increasing")]
+    UnsortedEndpoints,
+    #[error("Invalid tree state")]
+    InvalidState,
+    #[error("Epsilon must be non-negative")]
+    NegativeEpsilon,
+    #[error("Too many endpoints or bins, the result would exceed addressable memory")]
+    TooLarge,
+    #[error("Scale factor must be positive")]
+    NonPositiveFactor,
+    #[error("Number of bins must be positive")]
+    ZeroBins,
 }
 pub trait InputValidation{
     fn validate(self) -> Result<(), InputError>;
@@ -1,3 +1,5 @@
+import pickle
+import pytest
 import pysegtree
 from pysegtree import *
 
@@ -11,7 +13,168 @@ def test_basic():
     assert union.contains_point(1.5)
     assert union.contains_point(2.5)
     assert not union.contains_point(3.5)
+    assert union.intervals == [(1.0, 3.0)]
     s = segtree.root_stats
     assert s["length"] == 2.0
     assert s["max_ovp"] == 1
-    assert s["min_ovp"] == 0
\ No newline at end of file
+    assert s["min_ovp"] == 0
+
+def test_context_manager():
+    with SegmentTree([1.0, 2.0, 3.0]) as segtree:
+        segtree.add_segment((1.0, 3.0), 0)
+        assert segtree.root_stats["max_ovp"] == 1
+    assert segtree.root_stats["max_ovp"] == 0
+    assert not segtree.get_union((float('-inf'), float('inf'))).contains_point(1.5)
+
+def test_eq():
+    a = SegmentTree([1.0, 2.0, 3.0])
+    b = SegmentTree([3.0, 2.0, 1.0])
+    a.add_segment((1.0, 2.0), 0)
+    a.add_segment((2.0, 3.0), 1)
+    b.add_segment((2.0, 3.0), 1)
+    assert a != b
+    b.add_segment((1.0, 2.0), 0)
+    assert a == b
+    assert a != "not a tree"
+
+def test_pickle():
+    segtree = SegmentTree([1.0, 2.0, 3.0, 4.0])
+    segtree.add_segment((1.0, 3.0), 0)
+    segtree.add_segment((2.0, 4.0), 1)
+    restored = pickle.loads(pickle.dumps(segtree))
+    assert restored == segtree
+    assert restored.root_stats == segtree.root_stats
+    query = (float('-inf'), float('inf'))
+    assert restored.get_union(query).intervals == segtree.get_union(query).intervals
+
+def test_get_union_foreach():
+    segtree = SegmentTree([0.0, 1.0, 2.0, 3.0, 5.0, 6.0])
+    segtree.add_segment((0.0, 1.0), 0)
+    segtree.add_segment((1.0, 3.0), 1)
+    segtree.add_segment((5.0, 6.0), 2)
+    seen = []
+    segtree.get_union_foreach((-1.0, 10.0), lambda start, end: seen.append((start, end)))
+    assert seen == segtree.get_union((-1.0, 10.0)).intervals
+    first = []
+    def take_first(start, end):
+        first.append((start, end))
+        return False
+    segtree.get_union_foreach((-1.0, 10.0), take_first)
+    assert first == [(0.0, 3.0)]
+    for falsy in (None, 0, []):
+        calls = []
+        segtree.get_union_foreach((-1.0, 10.0), lambda start, end: calls.append(start) or falsy)
+        assert len(calls) == 2
+
+def test_all_stats():
+    segtree = SegmentTree([1.0, 2.0, 3.0])
+    segtree.add_segment((1.0, 3.0), 0)
+    stats = segtree.all_stats()
+    assert len(stats) == segtree.node_count
+    root = stats[0]
+    assert root["interval"] == (float('-inf'), float('inf'))
+    assert {k: root[k] for k in ("length", "max_ovp", "min_ovp")} == segtree.root_stats
+
+def test_try_contains_point():
+    segtree = SegmentTree([1.0, 2.0])
+    segtree.add_segment((1.0, 2.0), 0)
+    union = segtree.get_union((0.0, 3.0))
+    with pytest.raises(ValueError):
+        union.try_contains_point(float('nan'))
+    assert union.try_contains_point(1.5)
+    assert not union.try_contains_point(float('inf'))
+    assert not union.contains_point(float('nan'))
+
+def test_dedup_keyword():
+    assert SegmentTree([1.0, 1.0, 2.0]).node_count == 9
+    kept = SegmentTree([1.0, 1.0, 2.0], dedup=False)
+    assert kept.node_count == 13
+    assert kept.endpoints() == [1.0, 1.0, 2.0]
+    assert pickle.loads(pickle.dumps(kept)) == kept
+
+def test_segment_interval():
+    segtree = SegmentTree([1.0, 2.0, 3.0])
+    segtree.add_segment((1.5, 2.5), 4)
+    assert segtree.segment_interval(4) == (1.5, 2.5)
+    assert segtree.segment_interval(5) is None
+    assert pickle.loads(pickle.dumps(segtree)).segment_interval(4) == (1.5, 2.5)
+
+def test_remove_by_id():
+    segtree = SegmentTree([1.0, 3.0, 5.0])
+    segtree.add_segment((1.0, 5.0), 3)
+    assert segtree.remove(3)
+    assert not segtree.remove(3)
+    assert segtree.root_stats["max_ovp"] == 0
+
+def test_is_empty():
+    segtree = SegmentTree([1.0, 2.0, 3.0])
+    assert segtree.is_empty
+    assert not segtree
+    segtree.add_segment((1.0, 2.0), 0)
+    assert not segtree.is_empty
+    assert segtree
+
+def test_from_intervals():
+    segtree = SegmentTree.from_intervals([(1.0, 3.0), (2.0, 4.0)])
+    assert segtree.root_stats["max_ovp"] == 2
+    assert segtree.segment_interval(1) == (2.0, 4.0)
+    labelled = SegmentTree.from_intervals([(1.0, 3.0)], ids=[7])
+    assert labelled.segment_interval(7) == (1.0, 3.0)
+    with pytest.raises(ValueError):
+        SegmentTree.from_intervals([(1.0, 3.0)], ids=[])
+
+def test_remove_segments():
+    segtree = SegmentTree([1.0, 2.0, 3.0, 4.0])
+    intervals = [(1.0, 3.0), (2.0, 4.0), (2.0, 3.0), (1.0, 4.0)]
+    for id, interval in enumerate(intervals):
+        segtree.add_segment(interval, id)
+    assert segtree.root_stats["max_ovp"] == 4
+    segtree.remove_segments(intervals[:3], [0, 1, 2])
+    assert segtree.root_stats["max_ovp"] == 1
+    with pytest.raises(ValueError):
+        segtree.remove_segments(intervals, [0])
+
+def test_check_invariants():
+    segtree = SegmentTree([1.0, 2.0, 3.0])
+    segtree.check_invariants()
+    segtree.add_segment_deferred((1.0, 3.0), 0)
+    with pytest.raises(AssertionError):
+        segtree.check_invariants()
+    segtree.recompute_stats()
+    segtree.check_invariants()
+
+def test_builder_finish_once():
+    builder = SegmentTreeBuilder()
+    builder.push_end(2.0)
+    builder.push_end(1.0)
+    assert builder.finish().endpoints() == [1.0, 2.0]
+    with pytest.raises(RuntimeError):
+        builder.finish()
+    with pytest.raises(RuntimeError):
+        builder.push_end(3.0)
+
+def test_pickle_lazy():
+    segtree = SegmentTree.new_lazy()
+    segtree.add_segment((1.0, 3.0), 0)
+    restored = pickle.loads(pickle.dumps(segtree))
+    assert restored == segtree
+    restored.add_segment((4.0, 6.0), 1)
+    assert restored.endpoints() == [1.0, 3.0, 4.0, 6.0]
+    assert restored.covered_length == 4.0
+
+def test_epsilon_snapping():
+    segtree = SegmentTree([1.0, 1.0 + 1e-12, 2.0], epsilon=1e-9)
+    segtree.add_segment((1.0 + 1e-12, 2.0), 0)
+    assert segtree.segment_interval(0) == (1.0, 2.0)
+    assert segtree.covered_length == 1.0
+    assert segtree.depth_at_point(2.0 - 1e-12) == 1
+    restored = pickle.loads(pickle.dumps(segtree))
+    assert restored == segtree
+    assert restored.depth_at_point(2.0 - 1e-12) == 1
+
+def test_coverage_profile_too_many_bins():
+    segtree = SegmentTree([0.0, 2.0, 4.0])
+    segtree.add_segment((0.0, 2.0), 0)
+    assert segtree.coverage_profile((0.0, 4.0), 2) == [2.0, 0.0]
+    with pytest.raises(OverflowError):
+        segtree.coverage_profile((0.0, 4.0), 2**63 - 1)
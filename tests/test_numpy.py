@@ -0,0 +1,63 @@
+import numpy as np
+import pytest
+from pysegtree import *
+
+def test_from_array():
+    ends = [3.0, 1.0, 2.0, 1.0]
+    from_list = SegmentTree(ends)
+    from_array = SegmentTree.from_array(np.array(ends, dtype=np.float64))
+    assert from_array.endpoints() == from_list.endpoints()
+    for tree in (from_list, from_array):
+        tree.add_segment((1.0, 3.0), 0)
+    assert from_array.root_stats == from_list.root_stats
+    strided = SegmentTree.from_array(np.arange(8.0)[::2])
+    assert strided.endpoints() == [0.0, 2.0, 4.0, 6.0]
+
+def test_get_union_array():
+    tree = SegmentTree([1.0, 2.0, 2.5, 3.0, 5.0])
+    tree.add_segment((1.0, 2.0), 0)
+    tree.add_segment((2.5, 3.0), 1)
+    tree.add_segment((3.0, 5.0), 2)
+    query = (float('-inf'), float('inf'))
+    array = tree.get_union_array(query)
+    assert array.dtype == np.float64
+    assert array.shape == (2, 2)
+    assert [tuple(row) for row in array.tolist()] == tree.get_union(query).intervals
+    assert tree.get_union_array((6.0, 7.0)).shape == (0, 2)
+
+def test_to_arrays():
+    tree = SegmentTree([1.0, 2.0, 3.0])
+    tree.add_segment((1.0, 3.0), 0)
+    starts, ends, counts = tree.to_arrays()
+    assert len(starts) == len(ends) == len(counts) == tree.node_count
+    assert starts[0] == float('-inf') and ends[0] == float('inf')
+    assert counts.sum() == tree.storage_count
+
+def test_get_gaps_array():
+    tree = SegmentTree([1.0, 2.0, 3.0, 5.0])
+    tree.add_segment((2.0, 3.0), 0)
+    query = (0.0, 6.0)
+    array = tree.get_gaps_array(query)
+    assert array.dtype == np.float64
+    assert array.shape == (2, 2)
+    assert [tuple(row) for row in array.tolist()] == tree.get_gaps(query).intervals
+    tree.add_segment((0.0, 5.0), 1)
+    assert tree.get_gaps_array((1.0, 5.0)).shape == (0, 2)
+
+def test_overlap_counts():
+    tree = SegmentTree([1.0, 2.0, 3.0])
+    tree.add_segment((1.0, 3.0), 0)
+    counts = tree.overlap_counts(np.array([0.5, 1.5, 2.5]))
+    assert counts.tolist() == [0, 1, 1]
+    assert counts.tolist() == [tree.depth_at_point(p) for p in (0.5, 1.5, 2.5)]
+    assert tree.overlap_counts(np.array([], dtype=np.float64)).shape == (0,)
+    with pytest.raises(ValueError):
+        tree.overlap_counts(np.array([float('nan')]))
+
+def test_overlap_counts_at_endpoints():
+    for ends in ([1.0, 4.0, 7.0, 10.0], [0.0, 2.0, 4.0, 5.0, 7.0, 9.0]):
+        tree = SegmentTree(ends)
+        tree.add_segment((4.0, 7.0), 0)
+        points = np.array(ends)
+        expected = [1 if 4.0 < p <= 7.0 else 0 for p in ends]
+        assert tree.overlap_counts(points).tolist() == expected